@@ -0,0 +1,283 @@
+use anyhow::Result;
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, ArgMatches};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, NetworkMode, SafeMode};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Argument {
+    pub name: String,
+    pub nickname: String,
+    pub help_text: String,
+    #[serde(rename = "type")]
+    pub argument_type: String,
+    #[serde(default)]
+    pub choices: Vec<String>,
+    pub default: Option<String>,
+}
+
+/// Command line of the REPL, flags are named after the keys of the config file.
+///
+/// One letter nicknames are short flags like `-y`, longer ones are aliases like `--xo`.
+pub fn cli() -> clap::Command {
+    let arguments = serde_json::json!(
+        [
+            {
+                "name": "system_message",
+                "nickname": "s",
+                "help_text": "prompt / custom instructions for the language model",
+                "type": "str",
+            },
+            {
+                "name": "local",
+                "nickname": "l",
+                "help_text": "experimentally run the language model locally (via LM Studio)",
+                "type": "bool",
+            },
+            {
+                "name": "auto_run",
+                "nickname": "y",
+                "help_text": "automatically run generated code",
+                "type": "bool",
+            },
+            {
+                "name": "debug_mode",
+                "nickname": "d",
+                "help_text": "run in debug mode",
+                "type": "bool",
+            },
+            {
+                "name": "disable_procedures",
+                "nickname": "dp",
+                "help_text": "disables procedures (RAG of some common OI use-cases). disable to shrink system message. auto-disabled for non-OpenAI models",
+                "type": "bool",
+            },
+            {
+                "name": "model",
+                "nickname": "m",
+                "help_text": "language model to use",
+                "type": "str",
+            },
+            {
+                "name": "temperature",
+                "nickname": "t",
+                "help_text": "optional temperature setting for the language model",
+                "type": "float",
+            },
+            {
+                "name": "context_window",
+                "nickname": "c",
+                "help_text": "optional context window size for the language model",
+                "type": "int",
+            },
+            {
+                "name": "max_tokens",
+                "nickname": "x",
+                "help_text": "optional maximum number of tokens for the language model",
+                "type": "int",
+            },
+            {
+                "name": "max_output",
+                "nickname": "xo",
+                "help_text": "optional maximum number of characters for code outputs",
+                "type": "int",
+            },
+            {
+                "name": "max_output_tokens",
+                "nickname": "xot",
+                "help_text": "optional maximum number of tokens for code outputs",
+                "type": "int",
+            },
+            {
+                "name": "report_resources",
+                "nickname": "rr",
+                "help_text": "tell the language model how much time, CPU and memory each execution used",
+                "type": "bool",
+            },
+            {
+                "name": "timeout",
+                "nickname": "to",
+                "help_text": "optionally set the seconds a code block may run before it is killed, 0 for no limit",
+                "type": "float",
+            },
+            {
+                "name": "max_budget",
+                "nickname": "b",
+                "help_text": "optionally set the max budget (in USD) for your llm calls",
+                "type": "float",
+            },
+            {
+                "name": "api_base",
+                "nickname": "ab",
+                "help_text": "optionally set the API base URL for your llm calls (this will override environment variables)",
+                "type": "str",
+            },
+            {
+                "name": "api_key",
+                "nickname": "ak",
+                "help_text": "optionally set the API key for your llm calls (this will override environment variables)",
+                "type": "str",
+            },
+            {
+                "name": "safe_mode",
+                "nickname": "safe",
+                "help_text": "optionally enable safety mechanisms like code scanning; valid options are off, ask, and auto",
+                "type": "str",
+                "choices": ["off", "ask", "auto"],
+                "default": "off",
+            },
+            {
+                "name": "config_file",
+                "nickname": "cf",
+                "help_text": "optionally set a custom config file to use",
+                "type": "str",
+            },
+            {
+                "name": "policy_file",
+                "nickname": "pf",
+                "help_text": "optionally set a policy file of allowed and denied languages, executables, paths and environment variables",
+                "type": "str",
+            },
+            {
+                "name": "network",
+                "nickname": "net",
+                "help_text": "optionally restrict the network of executed code; valid options are full, none, and allowlist",
+                "type": "str",
+                "choices": ["full", "none", "allowlist"],
+                "default": "full",
+            },
+            {
+                "name": "allowed_hosts",
+                "nickname": "ah",
+                "help_text": "optionally set the comma separated hosts code may reach with --network allowlist",
+                "type": "str",
+            },
+            {
+                "name": "vision",
+                "nickname": "v",
+                "help_text": "experimentally use vision for supported languages (HTML)",
+                "type": "bool",
+            },
+            {
+                "name": "pty",
+                "nickname": "pt",
+                "help_text": "run shell commands on a pseudo-terminal and ask for the input they wait for",
+                "type": "bool",
+            },
+        ]
+    );
+
+    let mut clap = clap::Command::new("Open Interpreter").about("A Open Code Interpreter CLI");
+
+    {
+        // Deserialize the JSON array into a Vec<Argument>
+        let arguments: Vec<Argument> = serde_json::from_value(arguments).expect("Failed to deserialize JSON");
+
+        for arg in arguments {
+            let static_str: &'static str = Box::leak(arg.name.into_boxed_str());
+            let mut clap_arg = Arg::new(static_str).long(static_str);
+            let mut nickname = arg.nickname.chars();
+            clap_arg = match (nickname.next(), nickname.next()) {
+                (Some(short), None) => clap_arg.short(short),
+                _ => clap_arg.visible_alias(&*Box::leak(arg.nickname.into_boxed_str())),
+            };
+            clap_arg = match arg.argument_type.as_str() {
+                "bool" => {
+                    // `--no_auto_run` turns off what the config file turned on, the last of both wins.
+                    let negation: &'static str = Box::leak(format!("no_{static_str}").into_boxed_str());
+                    clap = clap.arg(
+                        Arg::new(negation)
+                            .long(negation)
+                            .action(ArgAction::SetTrue)
+                            .overrides_with(static_str)
+                            .help(format!("turn off --{static_str}")),
+                    );
+                    clap_arg.action(ArgAction::SetTrue).overrides_with(negation)
+                }
+                "int" => clap_arg.value_parser(clap::value_parser!(u32)),
+                "float" => clap_arg.value_parser(clap::value_parser!(f64)),
+                _ => clap_arg,
+            };
+            if !arg.choices.is_empty() {
+                let choices = arg.choices.into_iter().map(|choice| -> &'static str { Box::leak(choice.into_boxed_str()) });
+                clap_arg = clap_arg.value_parser(PossibleValuesParser::new(choices));
+            }
+            clap = clap.arg(match arg.default {
+                Some(default) => clap_arg.help(format!("{} [default: {default}]", arg.help_text)),
+                None => clap_arg.help(arg.help_text),
+            });
+        }
+    }
+
+    clap
+}
+
+/// Settings given on the command line, flags that were left out stay unset.
+pub fn config_from_matches(matches: &ArgMatches) -> Result<Config> {
+    let flag = |name: &str| {
+        if matches.get_flag(name) {
+            Some(true)
+        } else {
+            matches.get_flag(&format!("no_{name}")).then_some(false)
+        }
+    };
+    let string = |name: &str| matches.get_one::<String>(name).cloned();
+    Ok(Config {
+        system_message: string("system_message"),
+        local: flag("local"),
+        auto_run: flag("auto_run"),
+        debug_mode: flag("debug_mode"),
+        disable_procedures: flag("disable_procedures"),
+        model: string("model"),
+        temperature: matches.get_one::<f64>("temperature").map(|&temperature| temperature as f32),
+        context_window: matches.get_one::<u32>("context_window").copied(),
+        max_tokens: matches.get_one::<u32>("max_tokens").copied(),
+        max_output: matches.get_one::<u32>("max_output").map(|&max_output| max_output as usize),
+        max_output_tokens: matches.get_one::<u32>("max_output_tokens").copied(),
+        report_resources: flag("report_resources"),
+        timeout: matches.get_one::<f64>("timeout").copied(),
+        language_timeouts: None,
+        max_budget: matches.get_one::<f64>("max_budget").copied(),
+        api_base: string("api_base"),
+        api_key: string("api_key"),
+        safe_mode: string("safe_mode").map(|safe_mode| safe_mode.parse::<SafeMode>()).transpose()?,
+        vision: flag("vision"),
+        pty: flag("pty"),
+        config_file: string("config_file").map(Into::into),
+        policy_file: string("policy_file").map(Into::into),
+        network: string("network").map(|network| network.parse::<NetworkMode>()).transpose()?,
+        allowed_hosts: string("allowed_hosts").map(|hosts| hosts.split(',').map(|host| host.trim().to_string()).collect()),
+        sandbox: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nicknames() {
+        let matches = cli().try_get_matches_from(["interpreter", "-y", "-m", "gpt-4o", "--xo", "2000", "--safe", "ask"]).unwrap();
+        let config = config_from_matches(&matches).unwrap();
+        assert_eq!(config.auto_run, Some(true));
+        assert_eq!(config.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(config.max_output, Some(2000));
+        assert_eq!(config.safe_mode, Some(SafeMode::Ask));
+        assert_eq!(config.local, None);
+        assert!(cli().try_get_matches_from(["interpreter", "--safe_mode", "yolo"]).is_err());
+    }
+
+    #[test]
+    fn test_negated_flags() {
+        let config = |args: &[&str]| {
+            let matches = cli().try_get_matches_from(["interpreter"].iter().chain(args)).unwrap();
+            config_from_matches(&matches).unwrap()
+        };
+        assert_eq!(config(&["--no_auto_run"]).auto_run, Some(false));
+        assert_eq!(config(&["-y", "--no_auto_run"]).auto_run, Some(false));
+        assert_eq!(config(&["--no_auto_run", "-y"]).auto_run, Some(true));
+        assert_eq!(config(&["--no_pty", "--timeout", "30"]).pty, Some(false));
+        assert_eq!(config(&[]).vision, None);
+    }
+}
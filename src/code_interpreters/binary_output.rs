@@ -0,0 +1,207 @@
+use std::fmt::Write;
+
+use crate::code_interpreters::resource_usage::Bytes;
+
+/// Bytes of binary output shown as a hexdump.
+const PREVIEW_LEN: usize = 64;
+
+/// Most short lines held back to see whether binary output follows them.
+const MAX_PENDING: usize = 4;
+
+/// Magic numbers of common file formats, with the offset they are found at.
+const MAGIC: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (8, b"WEBP", "image/webp"),
+    (0, b"BM", "image/bmp"),
+    (0, b"II*\0", "image/tiff"),
+    (0, b"MM\0*", "image/tiff"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\0", "application/x-xz"),
+    (0, b"(\xb5/\xfd", "application/zstd"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\0asm", "application/wasm"),
+    (0, b"SQLite format 3\0", "application/vnd.sqlite3"),
+    (8, b"WAVE", "audio/wav"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1aE\xdf\xa3", "video/webm"),
+    (0, b"PAR1", "application/vnd.apache.parquet"),
+    (0, b"\x93NUMPY", "application/x-npy"),
+];
+
+/// Whether a line of output is binary data rather than text in some encoding.
+///
+/// NUL bytes give binary away, otherwise it is binary when over a quarter of it is control
+/// characters or invalid UTF-8. Short lines count as text, so a stray Latin-1 `é` is kept.
+pub fn is_binary(line: &[u8]) -> bool {
+    if line.contains(&0) {
+        return true;
+    }
+    if line.len() < 8 {
+        return false;
+    }
+    let text = String::from_utf8_lossy(line);
+    let suspicious = text
+        .chars()
+        .filter(|&c| c == char::REPLACEMENT_CHARACTER || (c.is_control() && !matches!(c, '\t' | '\r' | '\x1b' | '\x08' | '\x0c')))
+        .count();
+    suspicious * 4 > text.chars().count()
+}
+
+/// Whether a short line may be the start of binary data, like the `\x89PNG\r` a PNG starts with.
+fn is_suspicious(line: &[u8]) -> bool {
+    match std::str::from_utf8(line) {
+        Ok(text) => text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\r' | '\x1b')),
+        Err(_) => true,
+    }
+}
+
+/// Mime type of data starting with `head`, `application/octet-stream` if it is not recognized.
+pub fn mime_type(head: &[u8]) -> &'static str {
+    MAGIC
+        .iter()
+        .find(|(offset, magic, _)| head.get(*offset..offset + magic.len()) == Some(magic))
+        .map_or("application/octet-stream", |(_, _, mime)| mime)
+}
+
+/// `bytes` like `xxd` prints them, 16 per row with offsets and printable characters.
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", row * 16);
+        for (index, byte) in chunk.iter().enumerate() {
+            let gap = if index == 8 { "  " } else { " " };
+            let _ = write!(dump, "{gap}{byte:02x}");
+        }
+        let padding = (16 - chunk.len()) * 3 + usize::from(chunk.len() <= 8);
+        let ascii: String = chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        let _ = writeln!(dump, "{:padding$}  |{ascii}|", "");
+    }
+    dump
+}
+
+/// Consecutive binary lines of one stream, summarized instead of printed.
+#[derive(Debug, Default)]
+pub struct BinaryOutput {
+    head: Vec<u8>,
+    len: u64,
+}
+
+impl BinaryOutput {
+    /// Adds `line`, which lost its line ending when the output was split into lines.
+    pub fn push(&mut self, line: &[u8], newline: bool) {
+        for part in [line, if newline { b"\n".as_slice() } else { b"".as_slice() }] {
+            let room = PREVIEW_LEN.saturating_sub(self.head.len());
+            self.head.extend_from_slice(&part[..room.min(part.len())]);
+            self.len += part.len() as u64;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size, mime type and a hexdump of the start, ending with a newline. Empties it.
+    pub fn take_summary(&mut self) -> String {
+        let summary = format!(
+            "[{} of binary output ({}), not shown as text, first bytes:]\n{}",
+            Bytes(self.len),
+            mime_type(&self.head),
+            hexdump(&self.head)
+        );
+        *self = BinaryOutput::default();
+        summary
+    }
+}
+
+/// Turns the raw lines of one stream into text, decoded as UTF-8 with invalid bytes replaced
+/// and runs of binary lines summarized.
+#[derive(Debug, Default)]
+pub struct LineDecoder {
+    binary: BinaryOutput,
+    /// Short suspicious lines, which join the binary run if one follows.
+    pending: Vec<(Vec<u8>, bool)>,
+}
+
+impl LineDecoder {
+    /// Lines to pass on for `line`, which ended in a newline if `newline`.
+    pub fn push(&mut self, line: &[u8], newline: bool) -> Vec<String> {
+        let short = line.len() < 8 && is_suspicious(line);
+        if is_binary(line) || (short && !self.binary.is_empty()) {
+            for (pending, newline) in self.pending.drain(..) {
+                self.binary.push(&pending, newline);
+            }
+            self.binary.push(line, newline);
+            return Vec::new();
+        }
+        if short && self.pending.len() < MAX_PENDING {
+            self.pending.push((line.to_vec(), newline));
+            return Vec::new();
+        }
+        let mut lines = self.flush();
+        lines.push(String::from_utf8_lossy(line).into_owned());
+        lines
+    }
+
+    /// Lines held back so far, at the end of the output or of a block.
+    pub fn flush(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.binary.is_empty() {
+            lines.push(self.binary.take_summary().trim_end().to_string());
+        }
+        lines.extend(self.pending.drain(..).map(|(line, _)| String::from_utf8_lossy(&line).into_owned()));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(b"plain text with \x1b[31mcolor\x1b[0m"));
+        assert!(!is_binary("café crème brûlée".as_bytes()));
+        assert!(!is_binary(b"caf\xe9 cr\xe8me br\xfbl\xe9e"));
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+        assert!(is_binary(b"\xff\xd8\xff\xe0\x10JF\x01\x01\x01\xb4\xb4\xff\xdb"));
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(mime_type(b"RIFF\x24\0\0\0WAVEfmt "), "audio/wav");
+        assert_eq!(mime_type(b"\x01\x02"), "application/octet-stream");
+        let mut binary = BinaryOutput::default();
+        binary.push(b"\x89PNG\r", true);
+        binary.push(b"\x1a", true);
+        binary.push(&[0; 2000], false);
+        assert_eq!(
+            binary.take_summary(),
+            "[2.0 kB of binary output (image/png), not shown as text, first bytes:]\n\
+             00000000  89 50 4e 47 0d 0a 1a 0a  00 00 00 00 00 00 00 00  |.PNG............|\n\
+             00000010  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+             00000020  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+             00000030  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n"
+        );
+        assert!(binary.is_empty());
+        let mut decoder = LineDecoder::default();
+        assert_eq!(decoder.push(b"caf\xe9 cr\xe8me", true), ["caf\u{fffd} cr\u{fffd}me"]);
+        assert!(decoder.push(b"\x89PNG\r", true).is_empty());
+        assert!(decoder.push(b"\x1a", true).is_empty());
+        assert!(decoder.push(b"\0\0\0\rIHDR", true).is_empty());
+        let lines = decoder.push(b"done", true);
+        assert!(lines[0].starts_with("[17 B of binary output (image/png)"), "{lines:?}");
+        assert_eq!(lines[1], "done");
+        assert_eq!(hexdump(b"abc"), "00000000  61 62 63                                          |abc|\n");
+    }
+}
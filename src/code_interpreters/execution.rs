@@ -0,0 +1,264 @@
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::StreamExt;
+use tokio::time::{interval, sleep_until, Instant};
+use tracing::warn;
+
+use crate::code_interpreters::pty::InputPrompts;
+use crate::code_interpreters::resource_usage::{ResourceMeter, SAMPLE_INTERVAL};
+use crate::code_interpreters::{ExecutionResult, OutputChunk, SubprocessCodeInterpreter, Termination};
+
+/// How long interrupted code gets to wind down before its process group is killed.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(3);
+
+/// Runs `code`, handing every chunk of output to `on_chunk` as soon as it is printed.
+///
+/// The code is killed along with its process group once `timeout` elapses. Ctrl-C interrupts
+/// it, and kills it if it has not wound down after a grace period. The resources it uses are
+/// measured along the way.
+///
+/// `on_chunk` gets the output as printed, colours included, while the result holds it
+/// [normalized](ExecutionResult::normalize) for the model.
+///
+/// When the code waits for input at a prompt, which interpreters on a pseudo-terminal can tell,
+/// `on_prompt` is asked for the answer to type. Without one the input is ended, the code is
+/// interrupted and the model told why. The timeout and Ctrl-C still apply while it is asked.
+pub async fn execute<F: Future<Output = Option<String>>>(
+    code_interpreter: &mut dyn SubprocessCodeInterpreter,
+    code: &str,
+    timeout: Option<Duration>,
+    mut on_chunk: impl FnMut(&OutputChunk),
+    mut on_prompt: impl FnMut(String) -> F,
+) -> Result<ExecutionResult> {
+    let started = Instant::now();
+    let mut result = ExecutionResult::new(code_interpreter.language());
+    if !code_interpreter.is_running() {
+        code_interpreter.start_process()?;
+    }
+    let interrupter = code_interpreter.interrupter();
+    let mut prompts = code_interpreter.input_prompts();
+    let mut meter = code_interpreter.pid().map(ResourceMeter::start);
+    let mut sampling = interval(SAMPLE_INTERVAL);
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut grace_deadline = None;
+    let mut answering: Option<(String, Pin<Box<F>>)> = None;
+
+    let mut kill = false;
+    {
+        let mut output = code_interpreter.run(code);
+        loop {
+            tokio::select! {
+                chunk = output.next() => match chunk {
+                    Some(chunk) => {
+                        let chunk = chunk?;
+                        on_chunk(&chunk);
+                        if let Some(meter) = &mut meter {
+                            meter.count(&chunk);
+                        }
+                        result.push(&chunk);
+                    }
+                    None => break,
+                },
+                _ = sleep_until_deadline(deadline), if result.status == Termination::Finished => {
+                    result.status = Termination::TimedOut;
+                    kill = true;
+                    break;
+                }
+                _ = tokio::signal::ctrl_c(), if result.status == Termination::Finished => {
+                    result.status = Termination::Interrupted;
+                    // The code is stopped, the answer to its prompt is no longer needed.
+                    answering = None;
+                    match interrupter.as_ref().map(|interrupt| interrupt()) {
+                        Some(Ok(())) => grace_deadline = Some(Instant::now() + INTERRUPT_GRACE),
+                        Some(Err(err)) => {
+                            warn!("Failed to interrupt the running code: {}", err);
+                            kill = true;
+                            break;
+                        }
+                        None => {
+                            kill = true;
+                            break;
+                        }
+                    }
+                }
+                Some(prompt) = next_prompt(&mut prompts), if result.status == Termination::Finished && answering.is_none() => {
+                    answering = Some((prompt.clone(), Box::pin(on_prompt(prompt))));
+                }
+                answer = next_answer(&mut answering) => {
+                    let Some((prompt, _)) = answering.take() else { continue };
+                    match answer {
+                        Some(answer) => {
+                            if let Some(Err(err)) = prompts.as_ref().map(|prompts| prompts.answer(&answer)) {
+                                warn!("Failed to answer the prompt: {}", err);
+                            }
+                        }
+                        None => {
+                            result.status = Termination::AwaitingInput;
+                            result.reason = Some(format!(
+                                "the code waited for input at `{}` and was stopped, run commands so they don't ask, \
+                                 e.g. with `--yes`, input piped in or credentials from the environment",
+                                prompt.trim()
+                            ));
+                            // Reads like `read` in the shell itself can't be interrupted, the end
+                            // of input stops them.
+                            if let Some(Err(err)) = prompts.as_ref().map(InputPrompts::end_input) {
+                                warn!("Failed to end the input of the code: {}", err);
+                            }
+                            match interrupter.as_ref().map(|interrupt| interrupt()) {
+                                Some(Ok(())) => grace_deadline = Some(Instant::now() + INTERRUPT_GRACE),
+                                _ => {
+                                    kill = true;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                _ = sleep_until_deadline(grace_deadline) => {
+                    kill = true;
+                    break;
+                }
+                _ = sampling.tick(), if meter.is_some() => {
+                    if let Some(meter) = &mut meter {
+                        meter.sample();
+                    }
+                }
+            }
+        }
+    }
+
+    // Measure before killing, the numbers of a dead process are gone.
+    result.resources = meter.map(ResourceMeter::finish);
+    if kill {
+        // Give kernels a chance to stop the running cell, processes are killed either way.
+        if let Some(interrupt) = &interrupter {
+            let _ = interrupt();
+        }
+        code_interpreter.terminate();
+        let killed = format!("the {} process was killed, variables and other state of earlier code are lost", code_interpreter.proper_name());
+        // The interpreter starts over on the next block, the model has to redo its setup.
+        result.reason = Some(match result.reason.take() {
+            Some(reason) => format!("{reason}, {killed}"),
+            None if result.status == Termination::TimedOut => {
+                format!("timed out after {}s, {killed}", timeout.unwrap_or_default().as_secs_f64())
+            }
+            None => killed,
+        });
+    } else {
+        result.exit_code = code_interpreter.exit_code();
+    }
+    result.duration = started.elapsed();
+    result.normalize();
+    Ok(result)
+}
+
+async fn next_prompt(prompts: &mut Option<InputPrompts>) -> Option<String> {
+    match prompts {
+        Some(prompts) => prompts.next().await,
+        None => future::pending().await,
+    }
+}
+
+async fn next_answer<F: Future<Output = Option<String>>>(answering: &mut Option<(String, Pin<Box<F>>)>) -> Option<String> {
+    match answering {
+        Some((_, answer)) => answer.await,
+        None => future::pending().await,
+    }
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_interpreters::languages::shell::Shell;
+    use crate::code_interpreters::OutputStream;
+
+    #[tokio::test]
+    async fn test_timeout_kills_process() {
+        let mut shell = Shell::new();
+        let result = execute(&mut shell, "sleep 30", Some(Duration::from_millis(300)), |_| {}, |_| async { None }).await.unwrap();
+        assert_eq!(result.status, Termination::TimedOut);
+        assert_eq!(result.exit_code, None);
+        assert_eq!(
+            result.reason.as_deref(),
+            Some("timed out after 0.3s, the Shell process was killed, variables and other state of earlier code are lost")
+        );
+        assert!(!shell.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_keeps_stdout_of_failed_command() {
+        let mut shell = Shell::new();
+        let result = execute(&mut shell, "echo partial; echo oops >&2; false", None, |_| {}, |_| async { None }).await.unwrap();
+        assert_eq!(result.status, Termination::Finished);
+        assert_eq!(result.exit_code, Some(1));
+        assert_eq!(result.stream_text(OutputStream::Stdout), "partial\n");
+        assert_eq!(result.stream_text(OutputStream::Stderr), "oops\n");
+        shell.terminate();
+    }
+
+    #[tokio::test]
+    async fn test_binary_and_latin1_output() {
+        let mut shell = Shell::new();
+        let code = r"printf '\211PNG\r\n\032\n\000\000\000\rIHDR\n'; printf 'caf\351 cr\350me\n'; printf 'no newline'";
+        let result = execute(&mut shell, code, None, |_| {}, |_| async { None }).await.unwrap();
+        let stdout = result.stream_text(OutputStream::Stdout);
+        assert!(stdout.starts_with("[17 B of binary output (image/png), not shown as text, first bytes:]\n00000000  89 50 4e 47"), "{stdout}");
+        assert!(stdout.ends_with("|.|\ncaf\u{fffd} cr\u{fffd}me\nno newline\n"), "{stdout}");
+        assert!(result.success());
+        shell.terminate();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_pty_prompts() {
+        let mut shell = Shell::with_pty();
+        let code = "[ -t 0 ] && [ -t 1 ] && echo tty; printf 'Continue? [y/N] '; read answer; echo \"got $answer\"";
+        let result = execute(&mut shell, code, None, |_| {}, |prompt| async move {
+            assert_eq!(prompt, "Continue? [y/N] ");
+            Some("y".to_string())
+        })
+        .await
+        .unwrap();
+        assert_eq!(result.stream_text(OutputStream::Stdout), "tty\nContinue? [y/N] got y\n");
+
+        let result = execute(&mut shell, "printf 'Password: '; read secret; echo done", None, |_| {}, |_| async { None }).await.unwrap();
+        assert_eq!(result.status, Termination::AwaitingInput);
+        assert!(result.reason.unwrap().contains("`Password:`"));
+        assert!(shell.is_running());
+        shell.terminate();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_timeout_while_prompt_is_answered() {
+        let mut shell = Shell::with_pty();
+        let timeout = Some(Duration::from_millis(500));
+        let code = "printf 'Name: '; read name";
+        let result = execute(&mut shell, code, timeout, |_| {}, |_| future::pending()).await.unwrap();
+        assert_eq!(result.status, Termination::TimedOut);
+        assert!(!shell.is_running());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_measures_resources() {
+        let mut shell = Shell::new();
+        let code = "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; head -c 1000 /dev/zero | tr '\\0' x; echo";
+        let result = execute(&mut shell, code, None, |_| {}, |_| async { None }).await.unwrap();
+        let resources = result.resources.unwrap();
+        assert_eq!(resources.stdout_bytes, 1001);
+        assert!(resources.cpu_user_secs.unwrap() + resources.cpu_system_secs.unwrap() > 0.0);
+        assert!(resources.peak_rss_bytes.unwrap() > 0);
+        shell.terminate();
+    }
+}
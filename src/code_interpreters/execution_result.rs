@@ -0,0 +1,356 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::code_interpreters::normalize::normalize;
+use crate::code_interpreters::resource_usage::ResourceUsage;
+use crate::code_interpreters::{OutputChunk, OutputStream};
+use crate::safety::Denial;
+
+/// Most output kept in memory for one execution, half from the start and half from the end.
+/// What is printed in between is dropped and `truncated` is set.
+pub const MAX_COLLECTED_OUTPUT: usize = 1 << 20;
+
+/// Why a block of code stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    Finished,
+    TimedOut,
+    Interrupted,
+    /// The user declined to run the block.
+    Declined,
+    /// Safe mode refused to run the block.
+    Refused,
+    /// The policy denies something the block uses, listed in `denials`.
+    Denied,
+    /// The block waited for input nobody gave and was stopped.
+    AwaitingInput,
+}
+
+/// Everything the model is told about one execution of the `execute` function.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionResult {
+    pub language: String,
+    /// Code that ran instead of the one the model wrote, when the user edited it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_code: Option<String>,
+    pub status: Termination,
+    /// Why the block did not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// What the policy denies in the block.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denials: Vec<Denial>,
+    /// Exit status of the block, `None` if the interpreter could not tell.
+    pub exit_code: Option<i32>,
+    #[serde(rename = "duration_secs", with = "duration_secs")]
+    pub duration: Duration,
+    /// Set when part of the output was dropped.
+    pub truncated: bool,
+    /// File with all of the output, set when part of it was left out of `output`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_output: Option<PathBuf>,
+    /// What the block used, left out of the message unless the session reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceUsage>,
+    /// Stdout and stderr in the order they were printed.
+    pub output: Vec<OutputChunk>,
+    /// Latest output once `output` holds half of [`MAX_COLLECTED_OUTPUT`], joined to it by
+    /// [`elide`](ExecutionResult::elide).
+    #[serde(skip)]
+    tail: VecDeque<OutputChunk>,
+    /// Characters dropped between `output` and `tail`.
+    #[serde(skip)]
+    dropped: usize,
+}
+
+impl ExecutionResult {
+    pub fn new(language: impl Into<String>) -> Self {
+        ExecutionResult {
+            language: language.into(),
+            edited_code: None,
+            status: Termination::Finished,
+            reason: None,
+            denials: Vec::new(),
+            exit_code: None,
+            duration: Duration::ZERO,
+            truncated: false,
+            resources: None,
+            full_output: None,
+            output: Vec::new(),
+            tail: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Result of a block the user declined to run.
+    pub fn declined(language: impl Into<String>) -> Self {
+        ExecutionResult { status: Termination::Declined, ..ExecutionResult::new(language) }
+    }
+
+    /// Result of a block safe mode refused to run because of `reason`.
+    pub fn refused(language: impl Into<String>, reason: impl Into<String>) -> Self {
+        ExecutionResult { status: Termination::Refused, reason: Some(reason.into()), ..ExecutionResult::new(language) }
+    }
+
+    /// Result of a block the policy does not let run.
+    pub fn denied(language: impl Into<String>, denials: Vec<Denial>) -> Self {
+        ExecutionResult {
+            status: Termination::Denied,
+            reason: Some("the policy of this machine denies what the code uses, do not try to work around it".to_string()),
+            denials,
+            ..ExecutionResult::new(language)
+        }
+    }
+
+    /// Appends `chunk`, merging it into the previous chunk if both come from the same stream.
+    ///
+    /// Past half of [`MAX_COLLECTED_OUTPUT`] it goes to the tail, which keeps only the latest output.
+    pub fn push(&mut self, chunk: &OutputChunk) {
+        let mut content = chunk.content.as_str();
+        if self.tail.is_empty() {
+            let room = (MAX_COLLECTED_OUTPUT / 2).saturating_sub(self.output.iter().map(|chunk| chunk.content.len()).sum());
+            let head = &content[..floor_char_boundary(content, room)];
+            append(&mut self.output, chunk.stream, head);
+            content = &content[head.len()..];
+        }
+        if content.is_empty() {
+            return;
+        }
+        match self.tail.back_mut() {
+            Some(last) if last.stream == chunk.stream => last.content.push_str(content),
+            _ => self.tail.push_back(OutputChunk { stream: chunk.stream, content: content.to_string() }),
+        }
+
+        let mut excess = self.tail.iter().map(|chunk| chunk.content.len()).sum::<usize>().saturating_sub(MAX_COLLECTED_OUTPUT / 2);
+        while let Some(first) = self.tail.front_mut().filter(|_| excess > 0) {
+            if first.content.len() <= excess {
+                excess -= first.content.len();
+                self.dropped += first.content.chars().count();
+                self.tail.pop_front();
+            } else {
+                let mut end = excess;
+                while !first.content.is_char_boundary(end) {
+                    end += 1;
+                }
+                self.dropped += first.content[..end].chars().count();
+                first.content.drain(..end);
+                excess = 0;
+            }
+            self.truncated = true;
+        }
+    }
+
+    /// Strips escape sequences and overwritten progress bars from the output and folds
+    /// repeated lines, which only waste tokens of the model.
+    pub fn normalize(&mut self) {
+        self.output = normalize(&self.output);
+        self.tail = normalize(self.tail.make_contiguous()).into();
+    }
+
+    /// Keeps the first and last characters of the output, `max_chars` in total, with a marker
+    /// in place of the middle that points to `full_output` if given.
+    ///
+    /// Also joins the output collected past half of [`MAX_COLLECTED_OUTPUT`], which is why it is
+    /// called without a limit too. Returns whether anything was left out.
+    pub fn elide(&mut self, max_chars: Option<usize>, full_output: Option<&Path>) -> bool {
+        let head_chars: usize = self.output.iter().map(|chunk| chunk.content.chars().count()).sum();
+        let tail_chars: usize = self.tail.iter().map(|chunk| chunk.content.chars().count()).sum();
+        let chars = head_chars + tail_chars;
+        let max_chars = max_chars.unwrap_or(usize::MAX);
+        let mut output = std::mem::take(&mut self.output);
+        for chunk in self.tail.drain(..) {
+            append(&mut output, chunk.stream, &chunk.content);
+        }
+        if chars <= max_chars && self.dropped == 0 {
+            self.output = output;
+            return false;
+        }
+
+        // The cut always covers the output dropped while collecting.
+        let (keep_head, keep_tail) = if chars <= max_chars {
+            (head_chars, tail_chars)
+        } else if self.dropped > 0 {
+            let keep_head = (max_chars / 2).min(head_chars);
+            (keep_head, (max_chars - keep_head).min(tail_chars))
+        } else {
+            (max_chars / 2, max_chars - max_chars / 2)
+        };
+        let left_out = chars - keep_head - keep_tail + self.dropped;
+
+        // The marker goes to the stream of the first character left out.
+        let mut elided = slice_chunks(&output, 0, keep_head);
+        let stream = slice_chunks(&output, keep_head, chars)
+            .first()
+            .or(output.last())
+            .map_or(OutputStream::Stdout, |chunk| chunk.stream);
+        let newline = if elided.last().is_some_and(|chunk| !chunk.content.ends_with('\n')) { "\n" } else { "" };
+        let marker = match full_output {
+            Some(path) => format!("{newline}[... {left_out} characters left out, the full output is in {} ...]\n", path.display()),
+            None => format!("{newline}[... {left_out} characters left out ...]\n"),
+        };
+        append(&mut elided, stream, &marker);
+        for chunk in slice_chunks(&output, chars - keep_tail, chars) {
+            append(&mut elided, chunk.stream, &chunk.content);
+        }
+
+        self.output = elided;
+        self.dropped = 0;
+        self.truncated = true;
+        self.full_output = full_output.map(Path::to_path_buf);
+        true
+    }
+
+    pub fn output_len(&self) -> usize {
+        self.output.iter().chain(&self.tail).map(|chunk| chunk.content.len()).sum()
+    }
+
+    /// All output of `stream` that was kept.
+    pub fn stream_text(&self, stream: OutputStream) -> String {
+        self.output.iter().chain(&self.tail).filter(|chunk| chunk.stream == stream).map(|chunk| chunk.content.as_str()).collect()
+    }
+
+    pub fn success(&self) -> bool {
+        self.status == Termination::Finished && self.exit_code.unwrap_or(0) == 0
+    }
+
+    /// JSON sent back to the model as the content of the function message.
+    pub fn to_message_content(&self) -> String {
+        if self.tail.is_empty() && self.dropped == 0 {
+            return serde_json::to_string(self).unwrap_or_default();
+        }
+        let mut result = self.clone();
+        result.elide(None, None);
+        serde_json::to_string(&result).unwrap_or_default()
+    }
+}
+
+/// Appends `content` to `output`, merging it into the last chunk if that is of `stream` too.
+fn append(output: &mut Vec<OutputChunk>, stream: OutputStream, content: &str) {
+    if content.is_empty() {
+        return;
+    }
+    match output.last_mut() {
+        Some(last) if last.stream == stream => last.content.push_str(content),
+        _ => output.push(OutputChunk { stream, content: content.to_string() }),
+    }
+}
+
+/// Characters `start..end` of the output in `chunks`.
+fn slice_chunks(chunks: &[OutputChunk], start: usize, end: usize) -> Vec<OutputChunk> {
+    let mut slice = Vec::new();
+    let mut position = 0;
+    for chunk in chunks {
+        let len = chunk.content.chars().count();
+        let (from, to) = (start.max(position), end.min(position + len));
+        if from < to {
+            let content: String = chunk.content.chars().skip(from - position).take(to - from).collect();
+            append(&mut slice, chunk.stream, &content);
+        }
+        position += len;
+    }
+    slice
+}
+
+/// Largest char boundary of `text` not past `index`.
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+mod duration_secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        // Milliseconds are plenty for the model.
+        serializer.serialize_f64((duration.as_secs_f64() * 1000.0).round() / 1000.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_merges_same_stream() {
+        let mut result = ExecutionResult::new("shell");
+        result.push(&OutputChunk::stdout("a\n"));
+        result.push(&OutputChunk::stdout("b\n"));
+        result.push(&OutputChunk::stderr("warning\n"));
+        result.push(&OutputChunk::stdout("c\n"));
+        assert_eq!(
+            result.output,
+            [OutputChunk::stdout("a\nb\n"), OutputChunk::stderr("warning\n"), OutputChunk::stdout("c\n")]
+        );
+        assert_eq!(result.stream_text(OutputStream::Stdout), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn test_push_keeps_head_and_tail() {
+        let mut result = ExecutionResult::new("shell");
+        result.push(&OutputChunk::stdout("é".repeat(MAX_COLLECTED_OUTPUT)));
+        result.push(&OutputChunk::stderr("done\n"));
+        assert!(result.truncated);
+        assert!(result.output_len() <= MAX_COLLECTED_OUTPUT);
+        assert_eq!(result.stream_text(OutputStream::Stderr), "done\n");
+
+        let left_out = MAX_COLLECTED_OUTPUT - (result.output_len() - "done\n".len()) / 2;
+        assert!(result.elide(None, None));
+        assert!(result.stream_text(OutputStream::Stdout).contains(&format!("é\n[... {left_out} characters left out ...]\né")));
+        assert_eq!(result.output.last(), Some(&OutputChunk::stderr("done\n")));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut result = ExecutionResult::new("shell");
+        result.push(&OutputChunk::stdout("\x1b[32mok\x1b[0m\n"));
+        result.push(&OutputChunk::stdout(" 10%\r100%\n"));
+        result.normalize();
+        assert_eq!(result.output, [OutputChunk::stdout("ok\n100%\n")]);
+    }
+
+    #[test]
+    fn test_message_content() {
+        let mut result = ExecutionResult::new("python");
+        result.exit_code = Some(1);
+        result.duration = Duration::from_millis(1500);
+        result.push(&OutputChunk::stderr("boom\n"));
+        assert_eq!(
+            result.to_message_content(),
+            r#"{"language":"python","status":"finished","exit_code":1,"duration_secs":1.5,"truncated":false,"output":[{"stream":"stderr","content":"boom\n"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_elide_keeps_head_and_tail() {
+        let mut result = ExecutionResult::new("shell");
+        result.push(&OutputChunk::stdout("abc\n"));
+        result.push(&OutputChunk::stderr("déf\n"));
+        result.push(&OutputChunk::stdout("ghi\n"));
+        assert!(!result.elide(Some(12), None));
+        assert!(result.elide(Some(8), Some(Path::new("/tmp/out.txt"))));
+        assert_eq!(
+            result.output,
+            [
+                OutputChunk::stdout("abc\n"),
+                OutputChunk::stderr("[... 4 characters left out, the full output is in /tmp/out.txt ...]\n"),
+                OutputChunk::stdout("ghi\n"),
+            ]
+        );
+        assert!(result.truncated);
+        assert_eq!(result.full_output.as_deref(), Some(Path::new("/tmp/out.txt")));
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::future::Future;
+use std::path::Path;
+use std::sync::mpsc as std_mpsc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use jupyter_client::commands::Command as JupyterCommand;
+use jupyter_client::responses::{Response, ShellResponse};
+use jupyter_client::Client;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+
+use crate::code_interpreters::jupyter::iopub::{route_output, wait_subscribed, ExecutionOutput, KernelOutput, OutputRoute, Routing};
+use crate::code_interpreters::subprocess_code_interpreter::Interrupter;
+
+/// A request for the shell channel, answered on `reply` unless that was dropped first.
+enum ShellRequest {
+    KernelInfo { reply: oneshot::Sender<Result<Response>> },
+    Execute { code: String, tx: mpsc::UnboundedSender<KernelOutput>, reply: oneshot::Sender<Result<Response>> },
+}
+
+/// A request for the control channel, which the kernel answers even while it runs code.
+enum ControlRequest {
+    Interrupt,
+    Shutdown { done: std_mpsc::Sender<bool> },
+}
+
+/// Async handle on the connections to one kernel.
+///
+/// ZeroMQ calls block, so each connection lives on a thread of its own and is opened once for
+/// the life of the kernel: one for the shell channel and its IOPub output, which handles one
+/// request at a time, and one for the control channel, so interrupts and shutdowns get through
+/// while code runs. Dropping the client ends the threads once their current request is answered.
+pub struct KernelClient {
+    shell: std_mpsc::Sender<ShellRequest>,
+    control: std_mpsc::Sender<ControlRequest>,
+}
+
+/// Code sent to the kernel, see [`KernelClient::execute`].
+///
+/// Dropping it cancels the execution: it is left out if it didn't start yet, and its output and
+/// reply are discarded otherwise. The code keeps running in the kernel until it is interrupted.
+pub struct Execution {
+    /// What the kernel publishes while running the code, closed once it went idle.
+    pub outputs: mpsc::UnboundedReceiver<KernelOutput>,
+    /// The `execute_reply` of the kernel.
+    pub reply: oneshot::Receiver<Result<Response>>,
+}
+
+impl KernelClient {
+    /// Connects to the kernel of `connection_file`, named `name` in errors and thread names.
+    ///
+    /// Connecting doesn't wait for the kernel, requests are sent once it listens.
+    pub fn connect(name: &str, connection_file: &Path) -> Result<KernelClient> {
+        let open = || {
+            Client::from_reader(File::open(connection_file)?).map_err(|err| anyhow!("failed to connect to the {name} kernel: {err}"))
+        };
+        let (shell_client, control_client) = (open()?, open()?);
+
+        let (shell, requests) = std_mpsc::channel();
+        std::thread::Builder::new()
+            .name(format!("kernel-shell-{name}"))
+            .spawn(move || serve_shell(shell_client, requests))?;
+        let (control, requests) = std_mpsc::channel();
+        std::thread::Builder::new()
+            .name(format!("kernel-control-{name}"))
+            .spawn(move || serve_control(control_client, requests))?;
+        Ok(KernelClient { shell, control })
+    }
+
+    /// Sends a `kernel_info_request` right away, the returned future resolves to the reply.
+    pub fn kernel_info(&self) -> impl Future<Output = Result<Response>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let sent = self.shell.send(ShellRequest::KernelInfo { reply });
+        async move {
+            sent.map_err(|_| closed())?;
+            rx.await.map_err(|_| closed())?
+        }
+    }
+
+    /// Queues `code` to run after the requests sent before it.
+    pub fn execute(&self, code: String) -> Result<Execution> {
+        let (tx, outputs) = mpsc::unbounded_channel();
+        let (reply, rx) = oneshot::channel();
+        self.shell.send(ShellRequest::Execute { code, tx, reply }).map_err(|_| closed())?;
+        Ok(Execution { outputs, reply: rx })
+    }
+
+    /// Interrupts the running code with an `interrupt_request`, without waiting for the kernel.
+    pub fn interrupter(&self) -> Interrupter {
+        let control = self.control.clone();
+        Box::new(move || control.send(ControlRequest::Interrupt).map_err(|_| closed()))
+    }
+
+    /// Asks the kernel to shut down, waiting at most `grace` for it to agree. Blocks, so it is
+    /// called off the runtime, see [`Kernel::shutdown`](crate::code_interpreters::jupyter::Kernel::shutdown).
+    pub fn shutdown(&self, grace: Duration) -> bool {
+        let (done, rx) = std_mpsc::channel();
+        self.control.send(ControlRequest::Shutdown { done }).is_ok() && rx.recv_timeout(grace).unwrap_or(false)
+    }
+}
+
+fn closed() -> anyhow::Error {
+    anyhow!("the connection to the kernel is closed")
+}
+
+/// Answers shell requests one after the other until the [`KernelClient`] is dropped.
+///
+/// The IOPub subscription is checked on the first execution, when the kernel is known to be up,
+/// and handed to a thread routing its messages to the execution they belong to. Every execution
+/// is waited for, also when it was cancelled, so the router learns the `msg_id` of each one
+/// from its reply before the next one is sent.
+fn serve_shell(client: Client, requests: std_mpsc::Receiver<ShellRequest>) {
+    let mut subscription = Some(client.iopub_subscribe().map_err(|err| anyhow!("failed to subscribe to the kernel output: {err}")));
+    let mut routing: Option<std_mpsc::Sender<Routing>> = None;
+    for request in requests {
+        match request {
+            ShellRequest::KernelInfo { reply } => {
+                if !reply.is_closed() {
+                    let _ = reply.send(client.send_shell_command(JupyterCommand::KernelInfo).map_err(|err| anyhow!("{err}")));
+                }
+            }
+            ShellRequest::Execute { code, tx, reply } => {
+                if reply.is_closed() {
+                    debug!("Skipping a cancelled execution");
+                    continue;
+                }
+                if let Some(subscription) = subscription.take() {
+                    routing = match subscribe(&client, subscription) {
+                        Ok(routing) => Some(routing),
+                        Err(err) => {
+                            let _ = reply.send(Err(err));
+                            return;
+                        }
+                    };
+                }
+                let route = OutputRoute { output: ExecutionOutput::new(None), tx };
+                let Some(routing) = routing.as_ref().filter(|routing| routing.send(Routing::Route(route)).is_ok()) else {
+                    let _ = reply.send(Err(anyhow!("the kernel output is no longer read")));
+                    return;
+                };
+                let command = JupyterCommand::Execute {
+                    code,
+                    silent: false,
+                    store_history: true,
+                    user_expressions: Default::default(),
+                    // Nothing answers on the stdin channel, `input()` fails instead of waiting forever.
+                    allow_stdin: false,
+                    stop_on_error: false,
+                };
+                let response = client.send_shell_command(command).map_err(|err| anyhow!("failed to send the code to the kernel: {err}"));
+                if let Ok(Response::Shell(ShellResponse::Execute { parent_header, .. })) = &response {
+                    let _ = routing.send(Routing::Replied(parent_header.msg_id.clone()));
+                }
+                let _ = reply.send(response);
+            }
+        }
+    }
+}
+
+/// Waits for `subscription` to get messages and starts routing them, see [`route_output`].
+fn subscribe(client: &Client, subscription: Result<std_mpsc::Receiver<Response>>) -> Result<std_mpsc::Sender<Routing>> {
+    let receiver = subscription?;
+    if !wait_subscribed(client, &receiver) {
+        warn!("The kernel output subscription got no messages, the start of the output may be missing");
+    }
+    let (routing, rx) = std_mpsc::channel();
+    std::thread::Builder::new().name("kernel-iopub".to_string()).spawn(move || route_output(receiver, rx))?;
+    Ok(routing)
+}
+
+/// Answers control requests until the [`KernelClient`] is dropped.
+fn serve_control(client: Client, requests: std_mpsc::Receiver<ControlRequest>) {
+    for request in requests {
+        match request {
+            ControlRequest::Interrupt => {
+                if let Err(err) = client.send_control_command(JupyterCommand::Interrupt) {
+                    warn!("Failed to interrupt the kernel: {err}");
+                }
+            }
+            ControlRequest::Shutdown { done } => {
+                let _ = done.send(client.send_control_command(JupyterCommand::Shutdown { restart: false }).is_ok());
+            }
+        }
+    }
+}
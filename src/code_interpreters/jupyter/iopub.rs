@@ -0,0 +1,362 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use jupyter_client::commands::Command as JupyterCommand;
+use jupyter_client::header::Header;
+use jupyter_client::responses::{ExecutionState, IoPubResponse, Response, StreamType};
+use jupyter_client::Client;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::code_interpreters::resource_usage::Bytes;
+use crate::code_interpreters::OutputChunk;
+
+/// How long to wait for IOPub messages before checking whether anyone still listens.
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often a `kernel_info_request` is sent to see whether a new subscription gets messages.
+const SUBSCRIBE_ATTEMPTS: usize = 20;
+
+/// Images shown in the terminal, in the order they are preferred.
+const IMAGE_TYPES: [&str; 2] = ["image/png", "image/jpeg"];
+
+/// Something the kernel published while running code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KernelOutput {
+    /// Text shown to the user and sent to the model.
+    Text(OutputChunk),
+    /// An image to show the user, its description comes as [`KernelOutput::Text`].
+    Image(Vec<u8>),
+}
+
+/// Turns the IOPub messages of one `execute_request` into output, leaving out those of other
+/// requests.
+#[derive(Debug, Default)]
+pub struct ExecutionOutput {
+    msg_id: Option<String>,
+    /// Set by `clear_output(wait=True)`, which clears once the next output arrives.
+    clear_pending: bool,
+    idle: bool,
+}
+
+impl ExecutionOutput {
+    /// Output of the request `msg_id`, or of the first `execute_request` seen if `None`.
+    pub fn new(msg_id: Option<String>) -> Self {
+        ExecutionOutput { msg_id, ..ExecutionOutput::default() }
+    }
+
+    /// Whether the kernel went idle after the request, nothing more comes for it then.
+    pub fn is_done(&self) -> bool {
+        self.idle
+    }
+
+    pub fn handle(&mut self, message: &IoPubResponse) -> Vec<KernelOutput> {
+        let parent = parent_header(message);
+        if parent.msg_type != "execute_request" {
+            return Vec::new();
+        }
+        match &self.msg_id {
+            Some(msg_id) if *msg_id != parent.msg_id => return Vec::new(),
+            Some(_) => {}
+            None => self.msg_id = Some(parent.msg_id.clone()),
+        }
+
+        let output = match message {
+            IoPubResponse::Status { content, .. } => {
+                self.idle = content.execution_state == ExecutionState::Idle;
+                return Vec::new();
+            }
+            IoPubResponse::ExecuteInput { .. } => return Vec::new(),
+            IoPubResponse::ClearOutput { content, .. } => {
+                if content.wait {
+                    self.clear_pending = true;
+                    return Vec::new();
+                }
+                self.clear_pending = false;
+                return vec![KernelOutput::Text(cleared())];
+            }
+            IoPubResponse::Stream { content, .. } => vec![KernelOutput::Text(match content.name {
+                StreamType::Stdout => OutputChunk::stdout(content.text.clone()),
+                StreamType::Stderr => OutputChunk::stderr(content.text.clone()),
+            })],
+            IoPubResponse::ExecuteResult { content, .. } => render_bundle(&content.data),
+            IoPubResponse::DisplayData { content, .. } => render_bundle(&content.data),
+            IoPubResponse::Error { content, .. } => {
+                let text = if content.traceback.is_empty() {
+                    format!("{}: {}", content.ename, content.evalue)
+                } else {
+                    content.traceback.join("\n")
+                };
+                vec![KernelOutput::Text(OutputChunk::stderr(text + "\n"))]
+            }
+        };
+        if std::mem::take(&mut self.clear_pending) {
+            return [KernelOutput::Text(cleared())].into_iter().chain(output).collect();
+        }
+        output
+    }
+}
+
+/// Tells the model the output before was cleared, the user's terminal can't take it back.
+fn cleared() -> OutputChunk {
+    OutputChunk::stdout("[the output above was cleared]\n")
+}
+
+/// Output for a mime bundle of a result or display: its plain text, and images to show with a
+/// note for the model that they were.
+fn render_bundle(data: &HashMap<String, String>) -> Vec<KernelOutput> {
+    let mut output = Vec::new();
+    let mut text = String::new();
+    if let Some(plain) = data.get("text/plain") {
+        text.push_str(plain.trim_end_matches('\n'));
+        text.push('\n');
+    }
+    let image = IMAGE_TYPES.iter().find_map(|mime| Some((mime, data.get(*mime)?)));
+    if let Some((mime, encoded)) = image {
+        // Base64 in JSON is often wrapped into lines.
+        let encoded: String = encoded.split_whitespace().collect();
+        match BASE64.decode(encoded) {
+            Ok(bytes) => {
+                text.push_str(&format!("[{mime} image of {} shown to the user]\n", Bytes(bytes.len() as u64)));
+                output.push(KernelOutput::Image(bytes));
+            }
+            Err(err) => warn!("Failed to decode {mime} output: {err}"),
+        }
+    }
+    if text.is_empty() {
+        let mut types: Vec<&str> = data.keys().map(String::as_str).collect();
+        types.sort_unstable();
+        text = format!("[{} output not shown as text]\n", types.join(", "));
+    }
+    output.insert(0, KernelOutput::Text(OutputChunk::stdout(text)));
+    output
+}
+
+fn parent_header(message: &IoPubResponse) -> &Header {
+    match message {
+        IoPubResponse::Status { parent_header, .. }
+        | IoPubResponse::ExecuteInput { parent_header, .. }
+        | IoPubResponse::Stream { parent_header, .. }
+        | IoPubResponse::ExecuteResult { parent_header, .. }
+        | IoPubResponse::Error { parent_header, .. }
+        | IoPubResponse::ClearOutput { parent_header, .. }
+        | IoPubResponse::DisplayData { parent_header, .. } => parent_header,
+    }
+}
+
+/// Waits until a new IOPub subscription gets messages, so the start of the output isn't lost
+/// while it connects. Sends `kernel_info_request`s on `shell` until their status shows up on
+/// `receiver`.
+pub fn wait_subscribed(shell: &Client, receiver: &Receiver<Response>) -> bool {
+    for _ in 0..SUBSCRIBE_ATTEMPTS {
+        if let Err(err) = shell.send_shell_command(JupyterCommand::KernelInfo) {
+            warn!("Failed to reach the kernel: {err}");
+            return false;
+        }
+        while let Ok(response) = receiver.recv_timeout(RECV_TIMEOUT) {
+            if let Response::IoPub(message) = response {
+                if parent_header(&message).msg_type == "kernel_info_request" {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Where the output of the execution running in a kernel goes.
+pub struct OutputRoute {
+    pub output: ExecutionOutput,
+    pub tx: mpsc::UnboundedSender<KernelOutput>,
+}
+
+/// What the shell channel tells [`route_output`].
+pub enum Routing {
+    /// Output of the next `execute_request` goes along this route.
+    Route(OutputRoute),
+    /// The kernel replied to the `execute_request` with this `msg_id`, read back from the
+    /// reply. Routes sent afterwards leave out what it publishes late.
+    Replied(String),
+}
+
+/// Sends the output in the IOPub messages of `receiver` along the latest route of `routing`,
+/// until the execution is done or nobody listens anymore.
+///
+/// The client doesn't tell us the `msg_id` of a request before it is sent, so a route takes
+/// the first `execute_request` that isn't among those replied to before it was sent. Requests
+/// are sent one at a time, after the previous one was replied to, so that is the one it was
+/// sent for. Blocks until `routing` is closed, so it runs on a thread of its own for as long as
+/// the kernel connection lives.
+pub fn route_output(receiver: Receiver<Response>, routing: Receiver<Routing>) {
+    let mut current: Option<OutputRoute> = None;
+    let mut replied: HashSet<String> = HashSet::new();
+    loop {
+        let message = match receiver.recv_timeout(RECV_TIMEOUT) {
+            Ok(Response::IoPub(message)) => Some(message),
+            Ok(Response::Shell(_)) | Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        // A route is sent before its code, so it is here before the first message of the code.
+        loop {
+            match routing.try_recv() {
+                Ok(Routing::Route(route)) => current = Some(route),
+                Ok(Routing::Replied(msg_id)) => {
+                    replied.insert(msg_id);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        let Some(route) = &mut current else {
+            continue;
+        };
+        if let Some(message) = message {
+            let msg_id = &parent_header(&message).msg_id;
+            // The reply to the routed request comes before the end of its output.
+            if route.output.msg_id.as_ref() == Some(msg_id) || !replied.contains(msg_id) {
+                for kernel_output in route.output.handle(&message) {
+                    if route.tx.send(kernel_output).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        if route.output.is_done() || route.tx.is_closed() {
+            current = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jupyter_client::responses::{
+        ClearOutputContent, DisplayDataContent, ErrorContent, StatusContent, StreamContent,
+    };
+
+    use super::*;
+
+    fn header(msg_id: &str, msg_type: &str) -> Header {
+        Header {
+            msg_id: msg_id.to_string(),
+            username: "user".to_string(),
+            session: "session".to_string(),
+            msg_type: msg_type.to_string(),
+            version: "5.3".to_string(),
+        }
+    }
+
+    fn stream(parent: &str, text: &str) -> IoPubResponse {
+        IoPubResponse::Stream {
+            header: header("out", "stream"),
+            parent_header: header(parent, "execute_request"),
+            metadata: HashMap::new(),
+            content: StreamContent { name: StreamType::Stdout, text: text.to_string() },
+        }
+    }
+
+    fn status(parent: &str, parent_type: &str, execution_state: ExecutionState) -> IoPubResponse {
+        IoPubResponse::Status {
+            header: header("status", "status"),
+            parent_header: header(parent, parent_type),
+            metadata: HashMap::new(),
+            content: StatusContent { execution_state },
+        }
+    }
+
+    #[test]
+    fn test_filters_by_request() {
+        let mut output = ExecutionOutput::new(None);
+        assert!(output.handle(&status("info", "kernel_info_request", ExecutionState::Idle)).is_empty());
+        assert!(!output.is_done());
+        assert_eq!(output.handle(&stream("a", "hello\n")), [KernelOutput::Text(OutputChunk::stdout("hello\n"))]);
+        assert!(output.handle(&stream("b", "other\n")).is_empty());
+        let error = IoPubResponse::Error {
+            header: header("err", "error"),
+            parent_header: header("a", "execute_request"),
+            metadata: HashMap::new(),
+            content: ErrorContent { ename: "ZeroDivisionError".to_string(), evalue: "division by zero".to_string(), traceback: Vec::new() },
+        };
+        assert_eq!(
+            output.handle(&error),
+            [KernelOutput::Text(OutputChunk::stderr("ZeroDivisionError: division by zero\n"))]
+        );
+        output.handle(&status("a", "execute_request", ExecutionState::Idle));
+        assert!(output.is_done());
+    }
+
+    #[test]
+    fn test_display_and_clear() {
+        let mut output = ExecutionOutput::new(Some("a".to_string()));
+        let display = IoPubResponse::DisplayData {
+            header: header("display", "display_data"),
+            parent_header: header("a", "execute_request"),
+            metadata: HashMap::new(),
+            content: DisplayDataContent {
+                data: HashMap::from([
+                    ("text/plain".to_string(), "<Figure size 640x480 with 1 Axes>".to_string()),
+                    ("image/png".to_string(), BASE64.encode(b"\x89PNG\r\n\x1a\n")),
+                ]),
+                metadata: serde_json::Value::Null,
+            },
+        };
+        assert_eq!(
+            output.handle(&display),
+            [
+                KernelOutput::Text(OutputChunk::stdout("<Figure size 640x480 with 1 Axes>\n[image/png image of 8 B shown to the user]\n")),
+                KernelOutput::Image(b"\x89PNG\r\n\x1a\n".to_vec()),
+            ]
+        );
+        let clear = IoPubResponse::ClearOutput {
+            header: header("clear", "clear_output"),
+            parent_header: header("a", "execute_request"),
+            metadata: HashMap::new(),
+            content: ClearOutputContent { wait: true },
+        };
+        assert!(output.handle(&clear).is_empty());
+        assert_eq!(
+            output.handle(&stream("a", "50%\n")),
+            [KernelOutput::Text(cleared()), KernelOutput::Text(OutputChunk::stdout("50%\n"))]
+        );
+        assert_eq!(render_bundle(&HashMap::from([("text/html".to_string(), "<b>x</b>".to_string())])), [
+            KernelOutput::Text(OutputChunk::stdout("[text/html output not shown as text]\n"))
+        ]);
+    }
+
+    #[test]
+    fn test_routes_output_to_latest_execution() {
+        let (messages, receiver) = std::sync::mpsc::channel();
+        let (routing, rx) = std::sync::mpsc::channel();
+        let router = std::thread::Builder::new().name("test-iopub".to_string()).spawn(move || route_output(receiver, rx)).unwrap();
+        let route = |tx| Routing::Route(OutputRoute { output: ExecutionOutput::new(None), tx });
+
+        let (first_tx, mut first) = mpsc::unbounded_channel();
+        routing.send(route(first_tx)).unwrap();
+        messages.send(Response::IoPub(stream("a", "first\n"))).unwrap();
+        assert_eq!(first.blocking_recv(), Some(KernelOutput::Text(OutputChunk::stdout("first\n"))));
+        routing.send(Routing::Replied("a".to_string())).unwrap();
+        messages.send(Response::IoPub(stream("a", "after the reply\n"))).unwrap();
+        assert_eq!(first.blocking_recv(), Some(KernelOutput::Text(OutputChunk::stdout("after the reply\n"))));
+
+        // The first execution was cancelled, and the second one too before it printed anything.
+        // What they print late is left out.
+        let (second_tx, second) = mpsc::unbounded_channel();
+        routing.send(route(second_tx)).unwrap();
+        drop(second);
+        routing.send(Routing::Replied("b".to_string())).unwrap();
+        let (third_tx, mut third) = mpsc::unbounded_channel();
+        routing.send(route(third_tx)).unwrap();
+        for message in
+            [stream("a", "late\n"), stream("b", "late\n"), stream("c", "third\n"), status("c", "execute_request", ExecutionState::Idle)]
+        {
+            messages.send(Response::IoPub(message)).unwrap();
+        }
+        assert_eq!(third.blocking_recv(), Some(KernelOutput::Text(OutputChunk::stdout("third\n"))));
+        assert_eq!(third.blocking_recv(), None);
+        assert_eq!(first.blocking_recv(), None);
+
+        drop(routing);
+        router.join().unwrap();
+    }
+}
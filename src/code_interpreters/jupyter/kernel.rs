@@ -0,0 +1,175 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, TcpListener};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::process::Command;
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+
+use crate::code_interpreters::jupyter::{InterruptMode, KernelClient, KernelSpec};
+#[cfg(unix)]
+use crate::code_interpreters::subprocess_code_interpreter::signal_process_group;
+use crate::code_interpreters::subprocess_code_interpreter::{Interrupter, Subprocess};
+use crate::code_interpreters::Sandbox;
+
+/// How long a kernel may take to answer its first request, importing a big Python environment
+/// is slow.
+pub const KERNEL_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a kernel gets to shut down cleanly before it is killed.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// How often a kernel is checked for having exited while we wait for it.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A kernel we launched, along with the connection file only it and we know and our
+/// connection to it.
+pub struct Kernel {
+    spec: KernelSpec,
+    /// Holds the connection file, removed with the kernel.
+    _dir: TempDir,
+    client: KernelClient,
+    ready: bool,
+}
+
+impl Kernel {
+    /// Starts the kernel of `spec` on a connection file with free ports and a fresh key, in
+    /// `sandbox` if given.
+    ///
+    /// The kernel takes a while to answer, see [`wait_ready`](Kernel::wait_ready).
+    pub fn launch(spec: KernelSpec, sandbox: Option<&Sandbox>) -> Result<(Kernel, Subprocess)> {
+        let dir = tempfile::Builder::new().prefix("code-interpreter-kernel-").tempdir()?;
+        let connection_file = dir.path().join(format!("kernel-{}.json", spec.name));
+        fs::write(&connection_file, serde_json::to_vec_pretty(&connection_info(&spec.name)?)?)?;
+
+        let argv = spec.command_line(&connection_file);
+        let mut cmd = Command::new(&argv[0]);
+        cmd.args(&argv[1..]).envs(&spec.env);
+        if let Some(sandbox) = sandbox {
+            sandbox.apply(&mut cmd);
+        }
+        let process = Subprocess::spawn(cmd)
+            .with_context(|| format!("failed to start the {} kernel with `{}`", spec.name, argv.join(" ")))?;
+        info!("Started the {} kernel with {}", spec.name, connection_file.display());
+        let client = KernelClient::connect(&spec.name, &connection_file)?;
+        Ok((Kernel { spec, _dir: dir, client, ready: false }, process))
+    }
+
+    pub fn spec(&self) -> &KernelSpec {
+        &self.spec
+    }
+
+    /// The connection to the kernel, shared by every request.
+    pub fn client(&self) -> &KernelClient {
+        &self.client
+    }
+
+    /// Waits until the kernel answers a `kernel_info_request`, failing if its `process` exits
+    /// first or it takes longer than [`KERNEL_STARTUP_TIMEOUT`].
+    pub async fn wait_ready(&mut self, process: &mut Subprocess) -> Result<()> {
+        if self.ready {
+            return Ok(());
+        }
+        // Requests wait for the kernel to bind its ports, so the first reply means it is ready.
+        let mut reply = Box::pin(self.client.kernel_info());
+        let deadline = Instant::now() + KERNEL_STARTUP_TIMEOUT;
+        loop {
+            tokio::select! {
+                reply = &mut reply => {
+                    reply.with_context(|| format!("the {} kernel failed to start", self.spec.name))?;
+                    self.ready = true;
+                    return Ok(());
+                }
+                _ = sleep(POLL_INTERVAL) => {
+                    if !process.is_running() {
+                        bail!(
+                            "the {} kernel exited while starting, check that `{}` works",
+                            self.spec.name,
+                            self.spec.argv[0]
+                        );
+                    }
+                    if Instant::now() > deadline {
+                        bail!("the {} kernel didn't answer within {}s", self.spec.name, KERNEL_STARTUP_TIMEOUT.as_secs());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Interrupts the code running in the kernel started as `pid`, the way its kernelspec asks.
+    pub fn interrupter(&self, pid: u32) -> Interrupter {
+        #[cfg(unix)]
+        if self.spec.interrupt_mode == InterruptMode::Signal {
+            return Box::new(move || signal_process_group(pid, libc::SIGINT));
+        }
+        let _ = pid;
+        self.client.interrupter()
+    }
+
+    /// Asks the kernel started as `process` to shut down and kills it if it didn't agree within
+    /// [`SHUTDOWN_GRACE`]. The waiting happens on a thread of its own, this returns right away.
+    pub fn shutdown(self, mut process: Subprocess) {
+        let name = self.spec.name.clone();
+        let shutdown = move || {
+            if process.is_running() && !self.client.shutdown(SHUTDOWN_GRACE) {
+                warn!("The {} kernel didn't shut down cleanly, killing it", self.spec.name);
+            }
+            process.kill();
+        };
+        // Failing to spawn drops `process`, which kills it.
+        if let Err(err) = std::thread::Builder::new().name(format!("kernel-shutdown-{name}")).spawn(shutdown) {
+            warn!("Failed to shut down the {name} kernel cleanly: {err}");
+        }
+    }
+}
+
+/// Contents of a connection file for a kernel on free local ports, signed with a random key.
+fn connection_info(kernel_name: &str) -> Result<Value> {
+    // All ports are held at once so they differ, the kernel binds them right after they are let go.
+    let listeners = (0..5).map(|_| TcpListener::bind((Ipv4Addr::LOCALHOST, 0))).collect::<io::Result<Vec<_>>>()?;
+    let ports = listeners.iter().map(|listener| Ok(listener.local_addr()?.port())).collect::<io::Result<Vec<_>>>()?;
+    Ok(json!({
+        "shell_port": ports[0],
+        "iopub_port": ports[1],
+        "stdin_port": ports[2],
+        "control_port": ports[3],
+        "hb_port": ports[4],
+        "ip": "127.0.0.1",
+        "key": random_key()?,
+        "transport": "tcp",
+        "signature_scheme": "hmac-sha256",
+        "kernel_name": kernel_name,
+    }))
+}
+
+/// 128 random bits in hex, from the kernel's random number generator.
+fn random_key() -> Result<String> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut bytes))
+        .context("failed to read a random key from /dev/urandom")?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_info() {
+        let info = connection_info("python3").unwrap();
+        let mut ports: Vec<_> = ["shell_port", "iopub_port", "stdin_port", "control_port", "hb_port"]
+            .iter()
+            .map(|port| info[port].as_u64().unwrap())
+            .collect();
+        ports.sort();
+        ports.dedup();
+        assert_eq!(ports.len(), 5);
+        assert_eq!(info["key"].as_str().unwrap().len(), 32);
+        assert_ne!(info["key"], connection_info("python3").unwrap()["key"]);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use tracing::debug;
+
+/// How a kernel wants to be interrupted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterruptMode {
+    /// `SIGINT`, like Ctrl-C in a terminal.
+    #[default]
+    Signal,
+    /// An `interrupt_request` on the control channel.
+    Message,
+}
+
+/// A kernel Jupyter knows how to launch, read from the `kernel.json` of a kernelspec directory.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct KernelSpec {
+    /// Name of the kernelspec directory, e.g. `python3`.
+    #[serde(skip)]
+    pub name: String,
+    /// Command line starting the kernel, with `{connection_file}` in place of the connection file.
+    pub argv: Vec<String>,
+    pub display_name: String,
+    #[serde(default)]
+    pub language: String,
+    /// Environment variables to set for the kernel.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub interrupt_mode: InterruptMode,
+    /// Directory of the kernelspec, `None` for [`KernelSpec::ipykernel`].
+    #[serde(skip)]
+    pub resource_dir: Option<PathBuf>,
+}
+
+impl KernelSpec {
+    /// Kernelspec the Python interpreter runs in.
+    pub const PYTHON: &'static str = "python3";
+
+    /// Finds the kernelspec `name` in [`kernel_dirs`] like `jupyter kernelspec list` does.
+    ///
+    /// `python3` falls back to [`KernelSpec::ipykernel`] when no such kernelspec is installed,
+    /// which is the case for a plain `pip install ipykernel`.
+    pub fn find(name: &str) -> Result<Self> {
+        let dirs = kernel_dirs();
+        if let Some(resource_dir) = dirs.iter().map(|dir| dir.join(name)).find(|dir| dir.join("kernel.json").is_file()) {
+            return KernelSpec::load(&resource_dir);
+        }
+        if name == Self::PYTHON {
+            return Ok(KernelSpec::ipykernel());
+        }
+        let searched: Vec<_> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+        bail!("no Jupyter kernel named `{name}` is installed, searched {}", searched.join(", "))
+    }
+
+    /// Every kernelspec installed in [`kernel_dirs`], ordered by name.
+    pub fn all() -> Vec<Self> {
+        KernelSpec::all_in(&kernel_dirs())
+    }
+
+    /// The kernelspecs in `dirs`, where one found first hides those of the same name after it.
+    pub fn all_in(dirs: &[PathBuf]) -> Vec<Self> {
+        let mut specs: BTreeMap<String, KernelSpec> = BTreeMap::new();
+        for dir in dirs {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let resource_dir = entry.path();
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if specs.contains_key(&name) || !resource_dir.join("kernel.json").is_file() {
+                    continue;
+                }
+                match KernelSpec::load(&resource_dir) {
+                    Ok(spec) => {
+                        specs.insert(name, spec);
+                    }
+                    Err(err) => debug!("Skipping kernelspec: {err:#}"),
+                }
+            }
+        }
+        specs.into_values().collect()
+    }
+
+    /// Reads the kernelspec in `resource_dir`, which is named after it.
+    pub fn load(resource_dir: &Path) -> Result<Self> {
+        let path = resource_dir.join("kernel.json");
+        let contents = fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut spec: KernelSpec =
+            serde_json::from_str(&contents).with_context(|| format!("invalid kernelspec {}", path.display()))?;
+        if spec.argv.is_empty() {
+            bail!("the kernelspec {} has no command line", path.display());
+        }
+        spec.name = resource_dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        spec.resource_dir = Some(resource_dir.to_path_buf());
+        Ok(spec)
+    }
+
+    /// `ipykernel` of the `python3` on the `PATH`, what Jupyter itself runs for `python3`.
+    pub fn ipykernel() -> Self {
+        KernelSpec {
+            name: Self::PYTHON.to_string(),
+            argv: ["python3", "-m", "ipykernel_launcher", "-f", "{connection_file}"].map(String::from).to_vec(),
+            display_name: "Python 3 (ipykernel)".to_string(),
+            language: "python".to_string(),
+            env: HashMap::new(),
+            interrupt_mode: InterruptMode::Signal,
+            resource_dir: None,
+        }
+    }
+
+    /// [`argv`](KernelSpec::argv) with the placeholders filled in.
+    pub fn command_line(&self, connection_file: &Path) -> Vec<String> {
+        let resource_dir = self.resource_dir.as_deref().map(Path::to_string_lossy).unwrap_or_default();
+        self.argv
+            .iter()
+            .map(|arg| {
+                arg.replace("{connection_file}", &connection_file.to_string_lossy())
+                    .replace("{resource_dir}", &resource_dir)
+            })
+            .collect()
+    }
+}
+
+/// Directories holding kernelspecs, in the order Jupyter searches them: `JUPYTER_PATH`, the
+/// user's data directory, the active virtualenv or conda environment and the system-wide ones.
+pub fn kernel_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = env::var_os("JUPYTER_PATH").map(|path| env::split_paths(&path).collect()).unwrap_or_default();
+    dirs.extend(user_data_dir());
+    for prefix in ["VIRTUAL_ENV", "CONDA_PREFIX"] {
+        dirs.extend(env::var_os(prefix).map(|prefix| PathBuf::from(prefix).join("share").join("jupyter")));
+    }
+    if cfg!(unix) {
+        dirs.extend(["/usr/local/share/jupyter", "/usr/share/jupyter"].map(PathBuf::from));
+    }
+    dirs.into_iter().map(|dir| dir.join("kernels")).collect()
+}
+
+/// `JUPYTER_DATA_DIR`, or where Jupyter keeps user data on this platform.
+fn user_data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("JUPYTER_DATA_DIR") {
+        return Some(dir.into());
+    }
+    if cfg!(windows) {
+        return env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("jupyter"));
+    }
+    let home = PathBuf::from(env::var_os("HOME")?);
+    if cfg!(target_os = "macos") {
+        return Some(home.join("Library").join("Jupyter"));
+    }
+    let data_home = env::var_os("XDG_DATA_HOME").map_or_else(|| home.join(".local").join("share"), PathBuf::from);
+    Some(data_home.join("jupyter"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_kernelspec() {
+        let dir = tempfile::tempdir().unwrap();
+        let resource_dir = dir.path().join("ir");
+        fs::create_dir(&resource_dir).unwrap();
+        fs::write(
+            resource_dir.join("kernel.json"),
+            r#"{"argv": ["R", "--slave", "-e", "IRkernel::main()", "--args", "{connection_file}"],
+                "display_name": "R", "language": "R", "interrupt_mode": "message"}"#,
+        )
+        .unwrap();
+        let spec = KernelSpec::load(&resource_dir).unwrap();
+        assert_eq!(spec.name, "ir");
+        assert_eq!(spec.language, "R");
+        assert_eq!(spec.interrupt_mode, InterruptMode::Message);
+        assert_eq!(
+            spec.command_line(Path::new("/run/kernel-1.json")),
+            ["R", "--slave", "-e", "IRkernel::main()", "--args", "/run/kernel-1.json"]
+        );
+
+        fs::write(resource_dir.join("kernel.json"), r#"{"argv": [], "display_name": "R"}"#).unwrap();
+        assert!(KernelSpec::load(&resource_dir).is_err());
+    }
+
+    #[test]
+    fn test_all_in_dirs() {
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        for (dir, name, language) in [(&first, "bash", "bash"), (&second, "bash", "sh"), (&second, "julia-1.10", "julia")] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            let spec = format!(r#"{{"argv": ["kernel", "{{connection_file}}"], "display_name": "{name}", "language": "{language}"}}"#);
+            fs::write(dir.path().join(name).join("kernel.json"), spec).unwrap();
+        }
+        fs::create_dir(second.path().join("broken")).unwrap();
+        fs::write(second.path().join("broken").join("kernel.json"), "{").unwrap();
+
+        let specs = KernelSpec::all_in(&[first.path().to_path_buf(), second.path().to_path_buf()]);
+        let found: Vec<_> = specs.iter().map(|spec| (spec.name.as_str(), spec.language.as_str())).collect();
+        assert_eq!(found, [("bash", "bash"), ("julia-1.10", "julia")]);
+    }
+
+    #[test]
+    fn test_ipykernel_fallback() {
+        let spec = KernelSpec::ipykernel();
+        assert_eq!(spec.command_line(Path::new("k.json")), ["python3", "-m", "ipykernel_launcher", "-f", "k.json"]);
+        assert_eq!(spec.interrupt_mode, InterruptMode::Signal);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::code_interpreters::jupyter::KernelSpec;
+use crate::safety::Analyzer;
+
+/// A language run in Jupyter kernels, named like in the `execute` schema.
+#[derive(Debug, PartialEq, Eq)]
+pub struct KernelLanguage {
+    pub language: &'static str,
+    pub proper_name: &'static str,
+    pub file_extension: &'static str,
+    /// How its code is checked before it runs, `None` if it can't be looked into.
+    pub analyzer: Option<Analyzer>,
+    /// `language` in the `kernel.json` of its kernels, compared ignoring case.
+    spec_language: &'static str,
+}
+
+/// ipykernel.
+pub static PYTHON: KernelLanguage = KernelLanguage {
+    language: "python",
+    proper_name: "Python",
+    file_extension: "py",
+    analyzer: Some(Analyzer::Python),
+    spec_language: "python",
+};
+
+/// IRkernel.
+pub static R: KernelLanguage =
+    KernelLanguage { language: "r", proper_name: "R", file_extension: "r", analyzer: None, spec_language: "r" };
+
+/// IJulia, whose kernelspecs are named after the Julia version like `julia-1.10`.
+pub static JULIA: KernelLanguage =
+    KernelLanguage { language: "julia", proper_name: "Julia", file_extension: "jl", analyzer: None, spec_language: "julia" };
+
+/// ijavascript, or Deno's kernel.
+pub static JAVASCRIPT: KernelLanguage = KernelLanguage {
+    language: "javascript",
+    proper_name: "JavaScript",
+    file_extension: "js",
+    analyzer: None,
+    spec_language: "javascript",
+};
+
+/// bash_kernel, whose code is checked like shell code.
+pub static BASH: KernelLanguage = KernelLanguage {
+    language: "bash",
+    proper_name: "Bash",
+    file_extension: "sh",
+    analyzer: Some(Analyzer::Shell),
+    spec_language: "bash",
+};
+
+/// Languages we know how to offer when a kernel for them is installed.
+pub static KERNEL_LANGUAGES: [&KernelLanguage; 5] = [&PYTHON, &R, &JULIA, &JAVASCRIPT, &BASH];
+
+impl KernelLanguage {
+    /// The language `spec` runs, if it is one of [`KERNEL_LANGUAGES`].
+    pub fn of(spec: &KernelSpec) -> Option<&'static KernelLanguage> {
+        KERNEL_LANGUAGES.into_iter().find(|language| language.spec_language.eq_ignore_ascii_case(&spec.language))
+    }
+
+    /// The one of [`KERNEL_LANGUAGES`] offered as `language`.
+    pub fn named(language: &str) -> Option<&'static KernelLanguage> {
+        KERNEL_LANGUAGES.into_iter().find(|known| known.language == language)
+    }
+}
+
+/// The kernel each language runs in among `specs`, the first one for it when there are several.
+pub fn kernel_languages(specs: &[KernelSpec]) -> Vec<(&'static KernelLanguage, &KernelSpec)> {
+    let mut languages: Vec<(&'static KernelLanguage, &KernelSpec)> = Vec::new();
+    for spec in specs {
+        match KernelLanguage::of(spec) {
+            Some(language) if !languages.iter().any(|(known, _)| *known == language) => languages.push((language, spec)),
+            _ => {}
+        }
+    }
+    languages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(name: &str, language: &str) -> KernelSpec {
+        KernelSpec { name: name.to_string(), language: language.to_string(), ..KernelSpec::ipykernel() }
+    }
+
+    #[test]
+    fn test_kernel_languages() {
+        let specs = [spec("ir", "R"), spec("julia-1.10", "julia"), spec("julia-1.9", "julia"), spec("scala", "scala"), spec("bash", "bash")];
+        let languages: Vec<_> =
+            kernel_languages(&specs).into_iter().map(|(language, spec)| (language.language, spec.name.as_str())).collect();
+        assert_eq!(languages, [("r", "ir"), ("julia", "julia-1.10"), ("bash", "bash")]);
+    }
+}
@@ -0,0 +1,10 @@
+pub mod client;
+pub mod iopub;
+pub mod kernel;
+pub mod kernelspec;
+pub mod languages;
+
+pub use client::{Execution, KernelClient};
+pub use kernel::Kernel;
+pub use kernelspec::{InterruptMode, KernelSpec};
+pub use languages::{kernel_languages, KernelLanguage};
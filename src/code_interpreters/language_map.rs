@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::code_interpreters::jupyter::{kernel_languages, KernelSpec};
+use crate::code_interpreters::languages::{applescript::AppleScript, jupyter::Jupyter, python::Python, shell::Shell};
+use crate::code_interpreters::SubprocessCodeInterpreter;
+
+/// Creates a fresh, not yet started interpreter.
+pub type InterpreterFactory = Arc<dyn Fn() -> Box<dyn SubprocessCodeInterpreter> + Send + Sync>;
+
+/// Registry mapping the `language` argument of the `execute` function to an interpreter.
+///
+/// New languages are added with [`LanguageMap::register`], the agent loop only ever talks to
+/// the registry. Names are matched ignoring case, the model may write `R` for `r`.
+#[derive(Clone)]
+pub struct LanguageMap {
+    factories: BTreeMap<String, InterpreterFactory>,
+}
+
+impl LanguageMap {
+    /// An empty registry.
+    pub fn new() -> Self {
+        LanguageMap { factories: BTreeMap::new() }
+    }
+
+    /// Registers `factory` for `language`, replacing any previous registration. It is offered in
+    /// lowercase.
+    pub fn register(
+        &mut self,
+        language: impl Into<String>,
+        factory: impl Fn() -> Box<dyn SubprocessCodeInterpreter> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.factories.insert(language.into().to_lowercase(), Arc::new(factory));
+        self
+    }
+
+    /// Registers a [`Jupyter`] interpreter for each language a kernel among `specs` runs, unless
+    /// the language has an interpreter already. Returns the languages added.
+    pub fn register_kernels(&mut self, specs: &[KernelSpec]) -> Vec<&'static str> {
+        let mut added = Vec::new();
+        for (language, spec) in kernel_languages(specs) {
+            if self.contains(language.language) {
+                continue;
+            }
+            let kernel_name = spec.name.clone();
+            self.register(language.language, move || Box::new(Jupyter::new(language, kernel_name.clone())));
+            added.push(language.language);
+        }
+        added
+    }
+
+    /// Creates an interpreter for `language`, if one is registered.
+    pub fn create(&self, language: &str) -> Option<Box<dyn SubprocessCodeInterpreter>> {
+        self.factories.get(&language.to_lowercase()).map(|factory| factory())
+    }
+
+    pub fn contains(&self, language: &str) -> bool {
+        self.factories.contains_key(&language.to_lowercase())
+    }
+
+    /// Registered languages, in the order they are offered to the model.
+    pub fn languages(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+}
+
+impl Default for LanguageMap {
+    /// The built-in languages.
+    fn default() -> Self {
+        let mut language_map = LanguageMap::new();
+        language_map
+            .register("applescript", || Box::new(AppleScript::new()))
+            .register("python", || Box::new(Python::new()))
+            .register("shell", || Box::new(Shell::new()));
+        language_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_languages() {
+        let language_map = LanguageMap::default();
+        assert_eq!(language_map.languages().collect::<Vec<_>>(), ["applescript", "python", "shell"]);
+        assert_eq!(language_map.create("shell").unwrap().language(), "shell");
+        assert!(language_map.create("cobol").is_none());
+    }
+
+    #[test]
+    fn test_register_replaces() {
+        let mut language_map = LanguageMap::default();
+        language_map.register("python", || Box::new(Jupyter::python()));
+        let start_cmd = language_map.create("python").unwrap().start_cmd();
+        assert!(start_cmd.as_std().get_args().any(|arg| arg == "ipykernel_launcher"));
+    }
+
+    #[test]
+    fn test_register_kernels() {
+        let mut language_map = LanguageMap::default();
+        let spec = |name: &str, language: &str| KernelSpec { name: name.to_string(), language: language.to_string(), ..KernelSpec::ipykernel() };
+        let added = language_map.register_kernels(&[spec("python3", "python"), spec("ir", "R")]);
+        assert_eq!(added, ["r"]);
+        assert_eq!(language_map.languages().collect::<Vec<_>>(), ["applescript", "python", "r", "shell"]);
+        let r = language_map.create("R").unwrap();
+        assert_eq!((r.language(), r.proper_name(), r.file_extension()), ("r", "R", "r"));
+        assert_eq!(language_map.create("python").unwrap().start_cmd().as_std().get_program(), "python3");
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_stream::try_stream;
+use futures::stream::{BoxStream, StreamExt};
+use jupyter_client::responses::{Response, ShellResponse, Status};
+use tokio::process::Command;
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+use viuer::Config as ViuerConfig;
+
+use crate::code_interpreters::jupyter::iopub::KernelOutput;
+use crate::code_interpreters::jupyter::kernel::POLL_INTERVAL;
+use crate::code_interpreters::jupyter::languages::{self, KernelLanguage};
+use crate::code_interpreters::jupyter::{Execution, Kernel, KernelSpec};
+use crate::code_interpreters::sandbox::Network;
+use crate::code_interpreters::subprocess_code_interpreter::{Interrupter, Subprocess, SubprocessCodeInterpreter};
+use crate::code_interpreters::{OutputChunk, Sandbox};
+
+/// How long to wait for the rest of the output after the kernel replied, in case the status
+/// ending it was missed.
+const IDLE_GRACE: Duration = Duration::from_secs(1);
+
+/// Widest an image is shown in the terminal, in columns.
+const IMAGE_WIDTH: u32 = 50;
+
+/// A language running in a Jupyter kernel, so rich outputs like plots can be displayed.
+///
+/// The kernel is launched on first use, restarted if it died and shut down on
+/// [`terminate`](SubprocessCodeInterpreter::terminate).
+pub struct Jupyter {
+    language: &'static KernelLanguage,
+    kernel_name: String,
+    process: Option<Subprocess>,
+    kernel: Option<Kernel>,
+    sandbox: Option<Sandbox>,
+    /// Set when the kernel was restarted after it died, until the code is told.
+    restarted: bool,
+}
+
+impl Jupyter {
+    /// `language` in the kernel of the kernelspec `kernel_name`.
+    pub fn new(language: &'static KernelLanguage, kernel_name: impl Into<String>) -> Self {
+        Jupyter { language, kernel_name: kernel_name.into(), process: None, kernel: None, sandbox: None, restarted: false }
+    }
+
+    /// Python in the `python3` kernel.
+    pub fn python() -> Self {
+        Jupyter::new(&languages::PYTHON, KernelSpec::PYTHON)
+    }
+}
+
+impl SubprocessCodeInterpreter for Jupyter {
+    fn language(&self) -> &'static str {
+        self.language.language
+    }
+
+    fn proper_name(&self) -> &'static str {
+        self.language.proper_name
+    }
+
+    fn file_extension(&self) -> &'static str {
+        self.language.file_extension
+    }
+
+    /// Command line of the kernelspec. The kernel is started with [`Kernel::launch`] instead,
+    /// which fills in the connection file.
+    fn start_cmd(&self) -> Command {
+        let argv = KernelSpec::find(&self.kernel_name).map(|spec| spec.argv).unwrap_or_default();
+        let mut cmd = Command::new(argv.first().map_or("jupyter", String::as_str));
+        cmd.args(argv.iter().skip(1));
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(&mut cmd);
+        }
+        cmd
+    }
+
+    /// Kernels can't have a network of their own, we talk to them over loopback.
+    fn set_sandbox(&mut self, sandbox: Sandbox) -> bool {
+        if sandbox.network == Network::None {
+            return false;
+        }
+        self.sandbox = Some(sandbox);
+        true
+    }
+
+    fn process(&mut self) -> &mut Option<Subprocess> {
+        &mut self.process
+    }
+
+    /// Launches a kernel from the kernelspec, replacing one that died.
+    fn start_process(&mut self) -> Result<()> {
+        if self.kernel.is_some() {
+            warn!("The {} kernel died, starting a new one", self.kernel_name);
+            self.restarted = true;
+        }
+        self.terminate();
+        let (kernel, process) = Kernel::launch(KernelSpec::find(&self.kernel_name)?, self.sandbox.as_ref())?;
+        self.kernel = Some(kernel);
+        self.process = Some(process);
+        Ok(())
+    }
+
+    fn interrupter(&mut self) -> Option<Interrupter> {
+        let pid = self.process.as_ref()?.id();
+        Some(self.kernel.as_ref()?.interrupter(pid))
+    }
+
+    /// Runs `code` in the kernel and streams what it publishes: printed text, results, displays
+    /// and errors, with images shown in the terminal.
+    fn run<'a>(&'a mut self, code: &'a str) -> BoxStream<'a, Result<OutputChunk>> {
+        try_stream! {
+            if !self.is_running() {
+                self.start_process()?;
+            }
+            if std::mem::take(&mut self.restarted) {
+                yield OutputChunk::stderr("The kernel died and was restarted, variables and imports of earlier code are gone.\n");
+            }
+            let (Some(kernel), Some(process)) = (self.kernel.as_mut(), self.process.as_mut()) else {
+                Err(anyhow!("the Jupyter kernel is not running"))?;
+                return;
+            };
+            kernel.wait_ready(process).await?;
+            process.set_exit_code(None);
+
+            // Dropping the execution when the stream is dropped cancels it.
+            let Execution { mut outputs, mut reply } = kernel.client().execute(code.to_string())?;
+            let mut replied: Option<Instant> = None;
+            loop {
+                let step = tokio::select! {
+                    output = outputs.recv() => Step::Output(output),
+                    reply = &mut reply, if replied.is_none() => Step::Replied(reply.map_err(|_| no_reply()).and_then(|reply| Ok(exit_code(&reply?)))),
+                    _ = sleep(POLL_INTERVAL) => Step::Poll,
+                };
+                match step {
+                    Step::Output(Some(KernelOutput::Text(chunk))) => yield chunk,
+                    Step::Output(Some(KernelOutput::Image(bytes))) => {
+                        if let Err(err) = show_image(&bytes) {
+                            warn!("Failed to show an image: {err}");
+                        }
+                    }
+                    Step::Output(None) => break,
+                    Step::Replied(exit_code) => {
+                        process.set_exit_code(exit_code?);
+                        replied = Some(Instant::now());
+                    }
+                    Step::Poll => {
+                        if !process.is_running() {
+                            yield OutputChunk::stderr("The kernel died while running the code, it is restarted on the next run.\n");
+                            return;
+                        }
+                        if replied.is_some_and(|replied| replied.elapsed() > IDLE_GRACE) {
+                            break;
+                        }
+                    }
+                }
+            }
+            // The output ends before the reply when the kernel goes idle first, or couldn't be read.
+            if replied.is_none() {
+                let reply = reply.await.map_err(|_| no_reply())??;
+                process.set_exit_code(exit_code(&reply));
+            }
+        }
+        .boxed()
+    }
+
+    /// Asks the kernel to shut down before killing it, without waiting for either.
+    fn terminate(&mut self) {
+        match (self.kernel.take(), self.process.take()) {
+            (Some(kernel), Some(process)) => kernel.shutdown(process),
+            (None, Some(mut process)) => process.kill(),
+            (_, None) => {}
+        }
+    }
+}
+
+enum Step {
+    Output(Option<KernelOutput>),
+    /// The kernel ran the code, with its exit code.
+    Replied(Result<Option<i32>>),
+    Poll,
+}
+
+fn no_reply() -> anyhow::Error {
+    anyhow!("the connection to the kernel closed before it replied")
+}
+
+/// 0 if the code ran without an exception, 1 if not, `None` for an unexpected reply.
+fn exit_code(reply: &Response) -> Option<i32> {
+    match reply {
+        Response::Shell(ShellResponse::Execute { content, .. }) => Some(i32::from(content.status != Status::Ok)),
+        _ => None,
+    }
+}
+
+/// Prints an image in the terminal, at most [`IMAGE_WIDTH`] columns wide.
+fn show_image(bytes: &[u8]) -> Result<()> {
+    let image = image::load_from_memory(bytes)?;
+    let config = ViuerConfig {
+        transparent: false,
+        absolute_offset: false,
+        x: 0,
+        y: 0,
+        restore_cursor: false,
+        width: Some(image.width().min(IMAGE_WIDTH)),
+        height: None,
+        truecolor: false,
+        use_kitty: true,
+        use_iterm: true,
+        premultiplied_alpha: false,
+    };
+    viuer::print(&image, &config)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Stdio;
+
+    use super::*;
+    use crate::code_interpreters::{execute, OutputStream};
+
+    #[tokio::test]
+    async fn test_run_in_kernel() {
+        let ipykernel = std::process::Command::new("python3").args(["-c", "import ipykernel"]).stderr(Stdio::null()).status();
+        if !ipykernel.is_ok_and(|status| status.success()) {
+            eprintln!("skipped, ipykernel is not installed");
+            return;
+        }
+        let mut python = Jupyter::python();
+        let timeout = Some(Duration::from_secs(120));
+        let result = execute(&mut python, "x = 1\nprint(x)", timeout, |_| {}, |_| async { None }).await.unwrap();
+        assert!(result.success(), "{result:?}");
+        assert_eq!(result.stream_text(OutputStream::Stdout), "1\n");
+
+        let result = execute(&mut python, "x / 0", timeout, |_| {}, |_| async { None }).await.unwrap();
+        assert_eq!(result.exit_code, Some(1));
+        assert!(result.stream_text(OutputStream::Stderr).contains("ZeroDivisionError"), "{result:?}");
+        python.terminate();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_sandbox_keeps_loopback() {
+        let mut python = Jupyter::python();
+        assert!(!python.set_sandbox(Sandbox::network_only(Network::None).unwrap()));
+        assert!(python.set_sandbox(Sandbox::network_only(Network::Proxy("http://127.0.0.1:3128".to_string())).unwrap()));
+        assert!(python.start_cmd().as_std().get_envs().any(|(name, _)| name == "HTTPS_PROXY"));
+    }
+}
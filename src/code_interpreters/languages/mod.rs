@@ -1,4 +1,4 @@
 pub mod shell;
 pub mod python;
-pub mod python_vision;
+pub mod jupyter;
 pub mod applescript;
@@ -1,16 +1,9 @@
 // use error_chain::error_chain;
 
-// use std::collections::HashSet;
-use std::io::Write;
-use std::error::Error;
-
-use std::process::{Command, Stdio};
-
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Style, ThemeSet};
-use syntect::parsing::SyntaxSet;
-use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+use tokio::process::Command;
 
+use crate::code_interpreters::subprocess_code_interpreter::{Subprocess, SubprocessCodeInterpreter};
+use crate::code_interpreters::Sandbox;
 
 // error_chain!{
 //    errors { CmdError }
@@ -20,113 +13,120 @@ use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
 //    }
 // }
 
-pub fn python_interpreter(code: &str) -> Result<String, Box<dyn Error>> {
-    // For Python code highlighting.
-    // Load these once at the start of your program
-    println!("\n =================================================");
-    let ps = SyntaxSet::load_defaults_newlines();
-    let ts = ThemeSet::load_defaults();
-    let syntax = ps.find_syntax_by_extension("py").unwrap();
-    let mut h = HighlightLines::new(syntax, &ts.themes["Solarized (dark)"]);
-    let hl_code = "\n".to_string() + code;
-    for line in LinesWithEndings::from(&hl_code) {
-        let ranges: Vec<(Style, &str)> = h.highlight_line(line, &ps).unwrap();
-        let escaped = as_24_bit_terminal_escaped(&ranges[..], false);
-        print!("{}", escaped);
+pub struct Python {
+    process: Option<Subprocess>,
+    sandbox: Option<Sandbox>,
+}
+
+impl Python {
+    const FILE_EXTENSION: &'static str = "py";
+    const PROPER_NAME: &'static str = "Python";
+
+    pub fn new() -> Self {
+        Python { process: None, sandbox: None }
     }
+}
 
-    // let plain = Style::default();
-
-    // let style = Style {
-    //     foreground: Color::WHITE,
-    //     background: Color::BLACK,
-    //     font_style: FontStyle::default(),
-    // };
-    // let s = as_24_bit_terminal_escaped(&[(plain, "")], true);
-    // print!("{}", s);
-    // Python code highlighting end.
-
-    println!("\n\n =================================================");
-    let mut child;
-    if code.starts_with("!") {
-        // println!("==================== pip ====================");
-
-        let cmd_code = code.replace("!pip ", "");
-        let cmd_code: Vec<_> = cmd_code.split(" ").collect();
-        // println!("================{:?}", cmd_code);
-        child = Command::new("pip3")
-            .args(cmd_code)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .unwrap();
-    } else {
-        let cmd_args: Vec<_> = "-i -q -u ".split(" ").collect();
-        child = Command::new("python3")
-            .args(cmd_args)
-            .stdin(Stdio::piped())
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
-
-        // let mut code = code.as_bytes();
-        // code += code + "\n";
-        // child.stdin
-        //     .as_mut()
-        //     .ok_or("Child process stdin has not been captured!")?
-        //     .write(code)?;
-
-        // child.stdin
-        //     .as_mut()
-        //     .ok_or("Child process stdin has not been captured!")?
-        //     .write_all(b"\t")?;
-
-        if let Some(mut stdin) = child.stdin.take() {
-            if let Err(err) = stdin.write_all(code.as_bytes()) {
-                eprintln!("Error writing to process stdin: {}", err);
-            }
-            if let Err(err) = stdin.flush() {
-                eprintln!("Error flushing process stdin: {}", err);
-            }
-        }
+impl Default for Python {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        // println!("==================== python ====================");
-        // let filename = "test.py";
-        // let mut filebuf = File::create(filename)?;
+impl SubprocessCodeInterpreter for Python {
+    fn language(&self) -> &'static str {
+        "python"
+    }
 
-        // write!(filebuf, "{}", code)?;
+    fn proper_name(&self) -> &'static str {
+        Self::PROPER_NAME
+    }
 
-        // // filebuf.write_all(code);
-        // child = Command::new("python3")
-        //     .args(&[filename])
-        //     .stdout(Stdio::piped())
-        //     .stderr(Stdio::piped())
-        //     .spawn()
-        //     .unwrap();
+    fn file_extension(&self) -> &'static str {
+        Self::FILE_EXTENSION
     }
 
-    let output = child.wait_with_output().expect("failed to wait on child");
-    // println!("output: \n{:?}", output);
-
-    let mut output_str: String = String::new();
-    if output.status.success() {
-        output_str = String::from_utf8(output.stdout)?;
-        // let words = output_str.split_whitespace()
-        //     .map(|s| s.to_lowercase())
-        //     .collect::<HashSet<_>>();
-        // println!("Found {} unique words:", words.len());
-        // println!("{:#?}", output_str);
-    } else {
-        output_str = String::from_utf8(output.stderr)?;
-        // println!("{:#?}", output_str);
+    fn start_cmd(&self) -> Command {
+        let mut cmd = Command::new("python3");
+        cmd.args(["-q", "-u", "-c", DRIVER]);
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(&mut cmd);
+        }
+        cmd
     }
 
-    // println!("output_str: \n{:?}", output_str);
+    fn process(&mut self) -> &mut Option<Subprocess> {
+        &mut self.process
+    }
 
-    Ok(output_str)
-}
+    fn set_sandbox(&mut self, sandbox: Sandbox) -> bool {
+        self.sandbox = Some(sandbox);
+        true
+    }
 
+    /// Encodes the block as one JSON line for the [`DRIVER`].
+    fn preprocess_code(&self, code: &str) -> String {
+        serde_json::to_string(&preprocess_python(code)).unwrap_or_default()
+    }
+}
 
+/// Reads one JSON encoded block per line from stdin and executes it in a namespace that lives
+/// as long as the process, echoing the value of a trailing expression like the REPL does.
+const DRIVER: &str = r###"
+import ast, json, os, sys, traceback
+
+def run(code, namespace):
+    tree = ast.parse(code, "<code>", "exec")
+    last = None
+    if tree.body and isinstance(tree.body[-1], ast.Expr):
+        last = ast.Expression(tree.body.pop().value)
+    exec(compile(tree, "<code>", "exec"), namespace)
+    if last is not None:
+        sys.displayhook(eval(compile(last, "<code>", "eval"), namespace))
+
+def main():
+    blocks = sys.stdin
+    # Code reading stdin must not swallow the next block.
+    sys.stdin = open(os.devnull)
+    namespace = {"__name__": "__main__", "__builtins__": __builtins__}
+    for block in blocks:
+        status = 0
+        try:
+            run(json.loads(block), namespace)
+        except SystemExit as e:
+            # Ends the block, not the session.
+            status = e.code if isinstance(e.code, int) else 1 if e.code else 0
+        except BaseException:
+            etype, value, tb = sys.exc_info()
+            # Hide the driver's own frames.
+            while tb is not None and tb.tb_frame.f_code.co_filename != "<code>":
+                tb = tb.tb_next
+            traceback.print_exception(etype, value, tb)
+            status = 130 if etype is KeyboardInterrupt else 1
+        sys.stdout.flush()
+        print("##end_of_execution##%d" % status, flush=True)
+        print("##end_of_execution##", file=sys.stderr, flush=True)
+
+main()
+"###;
+
+/// Turns notebook style `!pip install ...` lines into subprocess calls.
+pub fn preprocess_python(code: &str) -> String {
+    code.lines()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            match trimmed.strip_prefix('!') {
+                Some(command) => {
+                    let indent = &line[..line.len() - trimmed.len()];
+                    let command = serde_json::to_string(command).unwrap_or_default();
+                    format!("{indent}__import__(\"subprocess\").run({command}, shell=True)")
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
 // fn main() -> Result<()> {
 //     let mut child = Command::new("python").stdin(Stdio::piped())
@@ -153,4 +153,43 @@ pub fn python_interpreter(code: &str) -> Result<String, Box<dyn Error>> {
 //         let err = String::from_utf8(output.stderr)?;
 //         error_chain::bail!("External command failed:\n {}", err)
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::code_interpreters::{OutputChunk, OutputStream};
+
+    #[test]
+    fn test_preprocess_pip() {
+        let code = "!pip install requests\nimport requests";
+        assert_eq!(
+            preprocess_python(code),
+            "__import__(\"subprocess\").run(\"pip install requests\", shell=True)\nimport requests"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_python_keeps_state() {
+        let mut python = Python::new();
+        let _ = python.run("def answer():\n    return 42\n\nx = answer()").collect::<Vec<_>>().await;
+        let output = python.run("x").collect::<Vec<_>>().await;
+        let output: String = output.into_iter().map(|chunk| chunk.unwrap().content).collect();
+        assert_eq!(output, "42\n");
+
+        let output = python.run("1 / 0").collect::<Vec<_>>().await;
+        let output: Vec<OutputChunk> = output.into_iter().map(Result::unwrap).collect();
+        assert!(output.iter().all(|chunk| chunk.stream == OutputStream::Stderr));
+        let output: String = output.into_iter().map(|chunk| chunk.content).collect();
+        assert!(output.ends_with("ZeroDivisionError: division by zero\n"));
+        assert!(!output.contains("<string>"));
+        assert_eq!(python.exit_code(), Some(1));
+
+        let _ = python.run("import sys\nsys.exit(3)").collect::<Vec<_>>().await;
+        assert_eq!(python.exit_code(), Some(3));
+        assert!(python.is_running());
+        python.terminate();
+    }
+}
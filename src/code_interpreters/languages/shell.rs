@@ -1,142 +1,187 @@
 use std::env;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::fs;
 
 use anyhow::Result;
-
-// SubprocessCodeInterpreter is assumed to be implemented elsewhere
-pub trait SubprocessCodeInterpreter {
-    // Define the necessary methods for subprocess code interpretation
-}
+use tempfile::TempDir;
+use tokio::process::Command;
+use tracing::warn;
+
+#[cfg(unix)]
+use crate::code_interpreters::subprocess_code_interpreter::signal_process;
+use crate::code_interpreters::subprocess_code_interpreter::{
+    Interrupter, Subprocess, SubprocessCodeInterpreter, END_OF_EXECUTION,
+};
+use crate::code_interpreters::Sandbox;
+#[cfg(unix)]
+use crate::utils::process_tree::child_pids;
 
 pub struct Shell {
-    config: Config,
     start_cmd: String,
-}
-
-pub struct Config {
-    // Define the configuration structure
-    // Add any configuration parameters needed for Shell
+    process: Option<Subprocess>,
+    sandbox: Option<Sandbox>,
+    /// Set when the shell runs on a pseudo-terminal, holds the blocks it sources.
+    pty: Option<TempDir>,
 }
 
 impl Shell {
     const FILE_EXTENSION: &'static str = "sh";
     const PROPER_NAME: &'static str = "Shell";
 
-    fn new(config: Config) -> Self {
+    pub fn new() -> Self {
         let start_cmd = if cfg!(windows) {
             "cmd.exe".to_string()
         } else {
             env::var("SHELL").unwrap_or_else(|_| "bash".to_string())
         };
 
-        Shell { config, start_cmd }
+        Shell { start_cmd, process: None, sandbox: None, pty: None }
     }
 
-    fn preprocess_code(&self, code: &str) -> String {
-        preprocess_shell(code)
+    /// A shell whose commands run on a pseudo-terminal, falling back to pipes if the directory
+    /// for the blocks can't be created.
+    pub fn with_pty() -> Self {
+        let pty = tempfile::Builder::new()
+            .prefix("code-interpreter-pty-")
+            .tempdir()
+            .map_err(|err| warn!("Failed to set up the shell on a pseudo-terminal: {err}"))
+            .ok();
+        Shell { pty, ..Shell::new() }
     }
+}
 
-    fn line_postprocessor(&self, line: &str) -> String {
-        line.to_string()
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn detect_active_line(&self, line: &str) -> Option<usize> {
-        if line.contains("##active_line") {
-            let active_line_str = line.split("##active_line").nth(1)?.split("##").next()?;
-            active_line_str.parse().ok()
-        } else {
-            None
+impl SubprocessCodeInterpreter for Shell {
+    fn language(&self) -> &'static str {
+        "shell"
+    }
+
+    fn proper_name(&self) -> &'static str {
+        Self::PROPER_NAME
+    }
+
+    fn file_extension(&self) -> &'static str {
+        Self::FILE_EXTENSION
+    }
+
+    fn start_cmd(&self) -> Command {
+        let mut cmd = Command::new(&self.start_cmd);
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(&mut cmd);
         }
+        cmd
     }
 
-    fn detect_end_of_execution(&self, line: &str) -> bool {
-        line.contains("##end_of_execution##")
+    fn process(&mut self) -> &mut Option<Subprocess> {
+        &mut self.process
     }
-}
 
-fn preprocess_shell(code: &str) -> String {
-    // Your implementation for preprocessing goes here
-    // let mut processed_code = add_active_line_prints(code);
-    // processed_code.push_str("\necho \"##end_of_execution##\"");
-    code.into()
-}
+    fn set_sandbox(&mut self, sandbox: Sandbox) -> bool {
+        self.sandbox = Some(sandbox);
+        true
+    }
 
-fn add_active_line_prints(code: &str) -> String {
-    // Your implementation for adding active line prints goes here
-    let mut lines: Vec<String> = code.lines().enumerate().map(|(index, line)| {
-        format!("echo \"##active_line{}##\"\n{}", index + 1, line)
-    }).collect();
-    lines.join("\n")
-}
+    #[cfg(unix)]
+    fn spawn(&self) -> Result<Subprocess> {
+        match self.pty {
+            Some(_) => Subprocess::spawn_pty(self.start_cmd()),
+            None => Subprocess::spawn(self.start_cmd()),
+        }
+    }
 
-fn has_multiline_commands(script_text: &str) -> bool {
-    // Your implementation for detecting multiline commands goes here
-    let continuation_patterns = vec![
-        r"\\$",
-        r"\|$",
-        r"&&\s*$",
-        r"\|\|\s*$",
-        r"<\($",
-        r"\($",
-        r"{\s*$",
-        r"\bif\b",
-        r"\bwhile\b",
-        r"\bfor\b",
-        r"do\s*$",
-        r"then\s*$",
-    ];
-
-    script_text.lines().any(|line| {
-        continuation_patterns.iter().any(|pattern| {
-            regex::Regex::new(pattern).unwrap().is_match(line.trim_end())
-        })
-    })
+    /// On a pseudo-terminal the block is sourced from a file with the terminal as its input, so
+    /// commands in it can read answers to their prompts while the shell reads code from stdin.
+    fn preprocess_code(&self, code: &str) -> String {
+        let Some(dir) = &self.pty else {
+            return preprocess_shell(code);
+        };
+        let path = dir.path().join("block.sh");
+        if let Err(err) = fs::write(&path, code) {
+            warn!("Failed to write the block to {}: {err}", path.display());
+            return preprocess_shell(code);
+        }
+        let path = path.to_string_lossy().replace('\'', r"'\''");
+        format!(". '{path}' < /dev/tty; echo \"{END_OF_EXECUTION}$?\"; echo \"{END_OF_EXECUTION}\" >&2")
+    }
+
+    /// Interrupts the commands the shell is running but not the shell itself, so it goes on to
+    /// print the end of execution markers and keeps its working directory and environment.
+    fn interrupter(&mut self) -> Option<Interrupter> {
+        #[cfg(unix)]
+        {
+            let pid = self.process.as_ref()?.id();
+            Some(Box::new(move || {
+                for child in child_pids(pid) {
+                    let _ = signal_process(child, libc::SIGINT);
+                }
+                Ok(())
+            }))
+        }
+        #[cfg(not(unix))]
+        None
+    }
 }
 
-pub async fn run_shell_command(shell_code: &str) -> Result<String> {
-
-    let config = Config {
-        // Initialize configuration parameters
-    };
-    let shell = Shell::new(config);
-    println!("\n\n =================================================");
-    let processed_code = shell.preprocess_code(shell_code);
-    println!("─❯: {}", &processed_code);
-    println!("\n\n =================================================");
-    let child = Command::new(&shell.start_cmd)
-        .arg("-c")
-        .arg(&processed_code)
-        .stdin(Stdio::piped())
-        .stderr(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
-
-    let output = child.wait_with_output().expect("failed to wait on child");
-
-    let output_str;
-    if output.status.success() {
-        output_str = String::from_utf8(output.stdout)?;
-        // println!("{:#?}", output_str);
-    } else {
-        output_str = String::from_utf8(output.stderr)?;
-        // println!("{:#?}", output_str);
-    }
-
-    // println!("output_str: \n{:?}", output_str);
-
-    Ok(output_str)
+/// Runs the block with `/dev/null` as its input, so commands reading stdin like `cat` or
+/// `read` don't eat the lines after them, which the shell reads from the same pipe, and tells
+/// the session the block is done, on both streams, along with its exit status.
+fn preprocess_shell(code: &str) -> String {
+    format!("{{\n{code}\n}} < /dev/null\necho \"{END_OF_EXECUTION}$?\"\necho \"{END_OF_EXECUTION}\" >&2")
 }
 
 #[cfg(test)]
 mod tests {
+    use futures::StreamExt;
+
     use super::*;
 
+    async fn run(shell: &mut Shell, code: &str) -> String {
+        let output = shell.run(code).collect::<Vec<_>>();
+        let output = tokio::time::timeout(std::time::Duration::from_secs(10), output).await.expect("the block hung");
+        output.into_iter().map(|chunk| chunk.unwrap().content).collect()
+    }
+
     #[tokio::test]
     async fn test_shell_ls() {
-        // Example usage
-        let code = "ls";
-        let _ = run_shell_command(code).await;
-        // Further processing based on the interpreter's methods
+        let mut shell = Shell::new();
+        assert_eq!(run(&mut shell, "ls -d /").await, "/\n");
+        shell.terminate();
+    }
+
+    #[tokio::test]
+    async fn test_stdin_is_not_the_code() {
+        let mut shell = Shell::new();
+        assert_eq!(run(&mut shell, "cat\nread x\necho after \"$x\"").await, "after \n");
+        assert_eq!(run(&mut shell, "echo next").await, "next\n");
+        shell.terminate();
+    }
+
+    #[tokio::test]
+    async fn test_shell_keeps_state() {
+        let mut shell = Shell::new();
+        run(&mut shell, "cd /\nexport CODE_INTERPRETER_TEST=kept").await;
+        assert_eq!(run(&mut shell, "pwd; echo $CODE_INTERPRETER_TEST").await, "/\nkept\n");
+        shell.terminate();
+    }
+
+    #[tokio::test]
+    async fn test_shell_interrupt_keeps_session() {
+        let mut shell = Shell::new();
+        let _ = shell.run("cd /").collect::<Vec<_>>().await;
+        let interrupter = shell.interrupter().unwrap();
+        let mut output = shell.run("sleep 30; echo after");
+        let (chunks, _) = tokio::join!(output.by_ref().collect::<Vec<_>>(), async {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            interrupter().unwrap();
+        });
+        drop(output);
+        let output: String = chunks.into_iter().map(|chunk| chunk.unwrap().content).collect();
+        assert_eq!(output, "after\n");
+        assert!(shell.is_running());
+        shell.terminate();
     }
 }
@@ -1 +1,23 @@
-pub mod languages;
\ No newline at end of file
+pub mod binary_output;
+pub mod execution;
+pub mod execution_result;
+pub mod jupyter;
+pub mod language_map;
+pub mod languages;
+pub mod normalize;
+pub mod output_chunk;
+pub mod proxy;
+pub mod pty;
+pub mod resource_usage;
+pub mod sandbox;
+pub mod subprocess_code_interpreter;
+pub mod timeouts;
+
+pub use execution::execute;
+pub use execution_result::{ExecutionResult, Termination};
+pub use language_map::LanguageMap;
+pub use output_chunk::{OutputChunk, OutputStream};
+pub use resource_usage::ResourceUsage;
+pub use sandbox::Sandbox;
+pub use subprocess_code_interpreter::SubprocessCodeInterpreter;
+pub use timeouts::Timeouts;
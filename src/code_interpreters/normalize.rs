@@ -0,0 +1,100 @@
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::code_interpreters::OutputChunk;
+
+/// Runs of the same line longer than this are folded.
+const MAX_REPEATS: usize = 2;
+
+/// Output without colours, cursor movements and other escape sequences.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    static ESCAPE: OnceLock<Regex> = OnceLock::new();
+    let escape = ESCAPE.get_or_init(|| {
+        // CSI like colours, OSC like window titles and links, character sets, two byte escapes.
+        Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(\x07|\x1b\\)?|\x1b[()][0-9A-Za-z]|\x1b[@-Z\\-_]").unwrap()
+    });
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+    escape.replace_all(text, "")
+}
+
+/// The part of `line` left after it was overwritten with `\r`, like the last state of a
+/// progress bar. A `\r` ending the line, as in `\r\n`, overwrites nothing.
+pub fn collapse_carriage_returns(line: &str) -> &str {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    line.rsplit('\r').find(|part| !part.trim().is_empty()).unwrap_or("")
+}
+
+/// `line` as the model should see it, without escape sequences and overwritten text.
+pub fn normalize_line(line: &str) -> String {
+    collapse_carriage_returns(&strip_ansi(line)).to_string()
+}
+
+/// Normalizes every line of `output` and folds runs of the same line within a stream into the
+/// line and a count.
+pub fn normalize(output: &[OutputChunk]) -> Vec<OutputChunk> {
+    output
+        .iter()
+        .map(|chunk| {
+            let mut content = String::with_capacity(chunk.content.len());
+            let mut previous: Option<String> = None;
+            let mut repeats = 0;
+            for line in chunk.content.split_inclusive('\n') {
+                let newline = if line.ends_with('\n') { "\n" } else { "" };
+                let line = normalize_line(line.trim_end_matches('\n')) + newline;
+                if previous.as_deref() == Some(line.as_str()) {
+                    repeats += 1;
+                    continue;
+                }
+                fold(&mut content, previous.as_deref(), repeats);
+                content.push_str(&line);
+                previous = Some(line);
+                repeats = 1;
+            }
+            fold(&mut content, previous.as_deref(), repeats);
+            OutputChunk { stream: chunk.stream, content }
+        })
+        .collect()
+}
+
+/// Adds the repeats of `line` after its first occurrence, as a count if there are many.
+fn fold(content: &mut String, line: Option<&str>, repeats: usize) {
+    if repeats > MAX_REPEATS {
+        content.push_str(&format!("[... previous line repeated {} more times]\n", repeats - 1));
+    } else if let Some(line) = line {
+        for _ in 1..repeats {
+            content.push_str(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_line() {
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: \x1b]8;;https://x.y\x07link\x1b]8;;\x07"), "error: link");
+        assert_eq!(normalize_line("  0%|          |\r 50%|#####     |\r100%|##########|\r"), "100%|##########|");
+        assert_eq!(normalize_line("Downloading\r\x1b[K"), "Downloading");
+        assert_eq!(normalize_line("plain"), "plain");
+    }
+
+    #[test]
+    fn test_normalize_folds_repeats() {
+        let output = [
+            OutputChunk::stdout("start\nwaiting\nwaiting\nwaiting\nwaiting\ndone\nok\nok\n"),
+            OutputChunk::stderr("\x1b[33mwarning\x1b[0m\n"),
+        ];
+        assert_eq!(
+            normalize(&output),
+            [
+                OutputChunk::stdout("start\nwaiting\n[... previous line repeated 3 more times]\ndone\nok\nok\n"),
+                OutputChunk::stderr("warning\n"),
+            ]
+        );
+    }
+}
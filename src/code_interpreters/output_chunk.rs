@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// The pipe a piece of output was printed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A piece of output streamed by an interpreter while code is running.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputChunk {
+    pub stream: OutputStream,
+    pub content: String,
+}
+
+impl OutputChunk {
+    pub fn stdout(content: impl Into<String>) -> Self {
+        OutputChunk { stream: OutputStream::Stdout, content: content.into() }
+    }
+
+    pub fn stderr(content: impl Into<String>) -> Self {
+        OutputChunk { stream: OutputStream::Stderr, content: content.into() }
+    }
+}
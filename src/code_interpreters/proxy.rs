@@ -0,0 +1,235 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use tokio::io::{copy_bidirectional, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// Longest request head read from a client.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Request headers that only concern the hop to the proxy.
+const HOP_HEADERS: &[&str] = &["connection", "keep-alive", "proxy-authorization", "proxy-connection"];
+
+/// Hosts code may connect to, `pypi.org` allows the host and all its subdomains.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AllowList {
+    hosts: Vec<String>,
+}
+
+impl AllowList {
+    pub fn new(hosts: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let hosts = hosts
+            .into_iter()
+            .map(|host| host.as_ref().trim().trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        AllowList { hosts }
+    }
+
+    pub fn allows(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.hosts.iter().any(|allowed| {
+            host == *allowed || host.strip_suffix(allowed.as_str()).is_some_and(|subdomain| subdomain.ends_with('.'))
+        })
+    }
+
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+}
+
+/// HTTP(S) proxy on loopback that only lets connections to allowed hosts through.
+///
+/// HTTPS goes through `CONNECT` and stays encrypted end to end. Plain HTTP requests are sent
+/// with `Connection: close`, so a connection never reaches another host than the one checked.
+/// It stops when dropped.
+pub struct Proxy {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Proxy {
+    /// Starts the proxy on a free port, it must be called from within the Tokio runtime.
+    pub fn start(allow_list: AllowList) -> Result<Self> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let _runtime = tokio::runtime::Handle::try_current().map_err(|err| anyhow!("the proxy needs a Tokio runtime: {err}"))?;
+        let listener = TcpListener::from_std(listener)?;
+
+        let allow_list = Arc::new(allow_list);
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((client, _)) = listener.accept().await else {
+                    continue;
+                };
+                let allow_list = allow_list.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle(client, &allow_list).await {
+                        debug!("Proxy connection failed: {err}");
+                    }
+                });
+            }
+        });
+        info!("Started the network proxy on {addr}");
+        Ok(Proxy { addr, task })
+    }
+
+    /// URL to put in `HTTP_PROXY` and `HTTPS_PROXY`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle(client: TcpStream, allow_list: &AllowList) -> Result<()> {
+    let mut client = BufReader::new(client);
+    let mut head: Vec<String> = Vec::new();
+    let mut head_len = 0;
+    loop {
+        let mut line = String::new();
+        let read = client.read_line(&mut line).await?;
+        head_len += read;
+        if read == 0 || head_len > MAX_HEAD {
+            bail!("the request head is incomplete or too long");
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        head.push(line);
+    }
+    let request_line = head.first().ok_or_else(|| anyhow!("empty request"))?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return respond(&mut client, "400 Bad Request", "malformed request line").await;
+    };
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let Some((host, port)) = split_host_port(target, 443) else {
+            return respond(&mut client, "400 Bad Request", "malformed CONNECT target").await;
+        };
+        if !allow_list.allows(&host) {
+            info!("Blocked a connection to {host}");
+            return respond(&mut client, "403 Forbidden", &format!("{host} is not on the network allow-list")).await;
+        }
+        let mut upstream = TcpStream::connect((host.as_str(), port)).await?;
+        client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await?;
+        copy_bidirectional(&mut client, &mut upstream).await?;
+        return Ok(());
+    }
+
+    let Some(rest) = target.get(..7).filter(|scheme| scheme.eq_ignore_ascii_case("http://")).map(|_| &target[7..]) else {
+        return respond(&mut client, "400 Bad Request", "only absolute http:// URLs and CONNECT are proxied").await;
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    let Some((host, port)) = split_host_port(authority, 80) else {
+        return respond(&mut client, "400 Bad Request", "malformed URL").await;
+    };
+    if !allow_list.allows(&host) {
+        info!("Blocked a request to {host}");
+        return respond(&mut client, "403 Forbidden", &format!("{host} is not on the network allow-list")).await;
+    }
+
+    let mut upstream = TcpStream::connect((host.as_str(), port)).await?;
+    let mut request = format!("{method} {path} {version}\r\n");
+    for header in &head[1..] {
+        let name = header.split(':').next().unwrap_or_default().trim().to_ascii_lowercase();
+        if !HOP_HEADERS.contains(&name.as_str()) {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    upstream.write_all(request.as_bytes()).await?;
+    copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+async fn respond(client: &mut BufReader<TcpStream>, status: &str, message: &str) -> Result<()> {
+    let response =
+        format!("HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{message}", message.len());
+    client.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Host and port of `authority`, like `example.com:8080` or `[::1]`.
+fn split_host_port(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, authority)| authority);
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+    (!host.is_empty()).then(|| (host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[test]
+    fn test_allow_list() {
+        let allow_list = AllowList::new(["pypi.org", "*.internal.example.com"]);
+        assert!(allow_list.allows("pypi.org"));
+        assert!(allow_list.allows("files.PYPI.org."));
+        assert!(allow_list.allows("mirror.internal.example.com"));
+        assert!(!allow_list.allows("evilpypi.org"));
+        assert!(!allow_list.allows("example.com"));
+        assert_eq!(split_host_port("[::1]:8080", 80), Some(("::1".to_string(), 8080)));
+        assert_eq!(split_host_port("example.com", 443), Some(("example.com".to_string(), 443)));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_allowed_requests_only() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut server, _) = upstream.accept().await.unwrap();
+            let mut request = vec![0; 1024];
+            let read = server.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..read]).to_string();
+            let body = if request.starts_with("GET /hello HTTP/1.1\r\n") && request.contains("Connection: close") { "hi" } else { "??" };
+            server.write_all(format!("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{body}").as_bytes()).await.unwrap();
+        });
+
+        let proxy = Proxy::start(AllowList::new(["127.0.0.1"])).unwrap();
+        let request = |target: String| {
+            let addr = proxy.addr;
+            async move {
+                let mut client = TcpStream::connect(addr).await.unwrap();
+                let request = format!("GET {target} HTTP/1.1\r\nHost: x\r\nProxy-Connection: keep-alive\r\n\r\n");
+                client.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                client.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        };
+        let response = request(format!("http://127.0.0.1:{upstream_port}/hello")).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK") && response.ends_with("hi"), "{response}");
+        let response = request("http://example.com/".to_string()).await;
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{response}");
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use tokio::sync::{mpsc, watch};
+
+use crate::code_interpreters::binary_output::LineDecoder;
+use crate::code_interpreters::subprocess_code_interpreter::decode_line;
+use crate::code_interpreters::{OutputChunk, OutputStream};
+
+/// How long output must stall on an unfinished line like `Password:` before it counts as a prompt.
+pub const PROMPT_IDLE: Duration = Duration::from_millis(500);
+
+/// Same for lines that only end like a prompt, e.g. `Name:`, which programs also print before
+/// a slow result.
+pub const LOOSE_PROMPT_IDLE: Duration = Duration::from_secs(3);
+
+/// Size of the terminal, wide enough that lines are rarely wrapped.
+const COLUMNS: u16 = 200;
+const ROWS: u16 = 50;
+
+#[cfg(unix)]
+/// A pseudo-terminal, whose slave end becomes the terminal of a process.
+pub struct Pty {
+    pub master: File,
+    pub slave: OwnedFd,
+}
+
+#[cfg(unix)]
+impl Pty {
+    /// Opens a pty that doesn't echo its input and ends lines with `\n` alone.
+    pub fn open() -> Result<Self> {
+        let (mut master, mut slave) = (0, 0);
+        let mut size = libc::winsize { ws_row: ROWS, ws_col: COLUMNS, ws_xpixel: 0, ws_ypixel: 0 };
+        // SAFETY: openpty only writes the two fds, the name buffer and termios are not used.
+        if unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null_mut(), ptr::addr_of_mut!(size)) } != 0 {
+            bail!("failed to open a pseudo-terminal: {}", io::Error::last_os_error());
+        }
+        // SAFETY: both fds were just opened and are owned by nobody else.
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            // SAFETY: sets a flag on an fd we own, so they are not inherited by other children.
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+
+        // Code is written to the terminal, it should not come back as output.
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: tcgetattr fills `termios` when it succeeds, which is checked before it is read.
+        unsafe {
+            if libc::tcgetattr(slave.as_raw_fd(), termios.as_mut_ptr()) != 0 {
+                bail!("failed to read the terminal settings: {}", io::Error::last_os_error());
+            }
+            let mut termios = termios.assume_init();
+            termios.c_lflag &= !(libc::ECHO | libc::ECHONL);
+            termios.c_oflag &= !libc::ONLCR;
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                bail!("failed to set up the terminal: {}", io::Error::last_os_error());
+            }
+        }
+        Ok(Pty { master, slave })
+    }
+}
+
+#[cfg(unix)]
+/// Makes the pty on stdout the controlling terminal of the process being started, so it and its
+/// children find it as `/dev/tty`. Meant for `pre_exec`.
+pub fn set_controlling_terminal() -> io::Result<()> {
+    // SAFETY: setsid and ioctl are async-signal-safe and only touch the calling process.
+    unsafe {
+        if libc::setsid() == -1 || libc::ioctl(1, libc::TIOCSCTTY as _, 0) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// How long output has to stall on the unfinished `line` for it to count as a prompt, `None` if
+/// it doesn't look like one.
+pub fn prompt_idle(line: &str) -> Option<Duration> {
+    static PROMPT: OnceLock<Regex> = OnceLock::new();
+    static LOOSE_PROMPT: OnceLock<Regex> = OnceLock::new();
+    let prompt = PROMPT.get_or_init(|| {
+        Regex::new(r"(?i)(password|passphrase|\bpin\b|\[y/n\]|\(y/n\)|\(yes/no[^)]*\)|press (enter|return|any key))").unwrap()
+    });
+    let loose_prompt = LOOSE_PROMPT.get_or_init(|| Regex::new(r"[:?>]\s*$").unwrap());
+    if line.trim().is_empty() {
+        None
+    } else if prompt.is_match(line) {
+        Some(PROMPT_IDLE)
+    } else if loose_prompt.is_match(line) {
+        Some(LOOSE_PROMPT_IDLE)
+    } else {
+        None
+    }
+}
+
+/// Prompts the code running on a pty waits at, and a way to answer them.
+pub struct InputPrompts {
+    waiting: watch::Receiver<Option<String>>,
+    terminal: Arc<File>,
+}
+
+impl InputPrompts {
+    /// The next prompt the code stops at.
+    pub async fn next(&mut self) -> Option<String> {
+        loop {
+            self.waiting.changed().await.ok()?;
+            if let Some(prompt) = self.waiting.borrow_and_update().clone() {
+                return Some(prompt);
+            }
+        }
+    }
+
+    /// Types `answer` and Enter into the terminal.
+    pub fn answer(&self, answer: &str) -> Result<()> {
+        let mut terminal = self.terminal.as_ref();
+        terminal.write_all(answer.as_bytes())?;
+        terminal.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Types CTRL-D, so the code reading the terminal gets the end of its input.
+    pub fn end_input(&self) -> Result<()> {
+        self.terminal.as_ref().write_all(b"\x04")?;
+        Ok(())
+    }
+}
+
+/// Sender of prompts found by [`read_terminal`], and the terminal to answer them on.
+#[derive(Clone)]
+pub struct PromptSource {
+    waiting: watch::Sender<Option<String>>,
+    terminal: Arc<File>,
+}
+
+impl PromptSource {
+    pub fn new(terminal: File) -> Self {
+        PromptSource { waiting: watch::channel(None).0, terminal: Arc::new(terminal) }
+    }
+
+    /// Prompts from now on, earlier ones are left out.
+    pub fn subscribe(&self) -> InputPrompts {
+        InputPrompts { waiting: self.waiting.subscribe(), terminal: self.terminal.clone() }
+    }
+}
+
+#[cfg(unix)]
+/// Reads the output of the terminal `master` until every process closed it, sending it to `tx`
+/// line by line like [`forward_lines`](crate::code_interpreters::subprocess_code_interpreter)
+/// does for pipes. An unfinished line that looks like a prompt is sent to `prompts` once output
+/// stalled on it long enough, see [`prompt_idle`]. Blocks, so it runs on a thread of its own.
+pub fn read_terminal(mut master: File, tx: mpsc::UnboundedSender<OutputChunk>, prompts: PromptSource) {
+    let mut decoder = LineDecoder::default();
+    let mut partial: Vec<u8> = Vec::new();
+    let mut buffer = [0; 8192];
+    // When the unfinished line counts as a prompt, unless it was sent already.
+    let mut prompt_at: Option<Instant> = None;
+    loop {
+        let timeout = prompt_at.map_or(-1, |at| at.saturating_duration_since(Instant::now()).as_millis() as libc::c_int);
+        let mut poll = libc::pollfd { fd: master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: polls a single fd we own.
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout) };
+        if ready == 0 {
+            prompt_at = None;
+            prompts.waiting.send_replace(Some(String::from_utf8_lossy(&partial).into_owned()));
+            continue;
+        }
+        if ready < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        // Reading fails with EIO once the last process closed the slave end.
+        let read = match master.read(&mut buffer) {
+            Ok(read) if read > 0 => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            _ => break,
+        };
+        prompts.waiting.send_if_modified(|waiting| waiting.take().is_some());
+        partial.extend_from_slice(&buffer[..read]);
+        while let Some(end) = partial.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = partial.drain(..=end).collect();
+            for content in decode_line(&mut decoder, &line[..end], true) {
+                if tx.send(OutputChunk { stream: OutputStream::Stdout, content }).is_err() {
+                    return;
+                }
+            }
+        }
+        prompt_at = prompt_idle(&String::from_utf8_lossy(&partial)).map(|idle| Instant::now() + idle);
+    }
+    let mut lines = if partial.is_empty() { Vec::new() } else { decode_line(&mut decoder, &partial, false) };
+    lines.extend(decoder.flush());
+    for content in lines {
+        let _ = tx.send(OutputChunk { stream: OutputStream::Stdout, content });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_idle() {
+        assert_eq!(prompt_idle("[sudo] password for user: "), Some(PROMPT_IDLE));
+        assert_eq!(prompt_idle("Do you want to continue? [Y/n] "), Some(PROMPT_IDLE));
+        assert_eq!(prompt_idle("Are you sure you want to continue connecting (yes/no/[fingerprint])? "), Some(PROMPT_IDLE));
+        assert_eq!(prompt_idle("Name: "), Some(LOOSE_PROMPT_IDLE));
+        assert_eq!(prompt_idle("Downloading 45%"), None);
+        assert_eq!(prompt_idle("   "), None);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::code_interpreters::{OutputChunk, OutputStream};
+use crate::utils::process_tree::{cpu_times, process_tree, rss_bytes};
+
+/// How often the memory of a running block is sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Resources one execution used, `None` where they can't be measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// CPU seconds spent in user mode by the interpreter and the processes it started.
+    pub cpu_user_secs: Option<f64>,
+    /// CPU seconds spent in the kernel.
+    pub cpu_system_secs: Option<f64>,
+    /// Most memory the interpreter and its processes held at once.
+    pub peak_rss_bytes: Option<u64>,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+}
+
+impl ResourceUsage {
+    /// One line for the REPL, like `1.20s wall, 0.95s user, 0.10s sys, 52.3 MB peak, 1.2 kB out`.
+    pub fn summary(&self, wall: Duration) -> String {
+        let mut parts = vec![format!("{:.2}s wall", wall.as_secs_f64())];
+        if let (Some(user), Some(system)) = (self.cpu_user_secs, self.cpu_system_secs) {
+            parts.push(format!("{user:.2}s user"));
+            parts.push(format!("{system:.2}s sys"));
+        }
+        if let Some(peak) = self.peak_rss_bytes {
+            parts.push(format!("{} peak", Bytes(peak)));
+        }
+        parts.push(format!("{} out", Bytes(self.stdout_bytes + self.stderr_bytes)));
+        parts.join(", ")
+    }
+}
+
+/// Bytes in decimal units, like `52.3 MB`.
+pub struct Bytes(pub u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+        if self.0 < 1000 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64;
+        let mut unit = "";
+        for next in UNITS {
+            if value < 1000.0 {
+                break;
+            }
+            value /= 1000.0;
+            unit = next;
+        }
+        write!(f, "{value:.1} {unit}")
+    }
+}
+
+/// Measures the resources a block uses while it runs in the process `pid`.
+///
+/// CPU time is the difference over the process tree, which includes children that exited and
+/// were waited for. Memory is sampled, so very short-lived processes may be missed.
+pub struct ResourceMeter {
+    pid: u32,
+    start_cpu: Option<(Duration, Duration)>,
+    last_cpu: Option<(Duration, Duration)>,
+    peak_rss: Option<u64>,
+    usage: ResourceUsage,
+}
+
+impl ResourceMeter {
+    pub fn start(pid: u32) -> Self {
+        let start_cpu = tree_cpu_times(pid);
+        let mut meter = ResourceMeter { pid, start_cpu, last_cpu: start_cpu, peak_rss: None, usage: ResourceUsage::default() };
+        meter.sample();
+        meter
+    }
+
+    /// Counts the bytes of `chunk`.
+    pub fn count(&mut self, chunk: &OutputChunk) {
+        let bytes = chunk.content.len() as u64;
+        match chunk.stream {
+            OutputStream::Stdout => self.usage.stdout_bytes += bytes,
+            OutputStream::Stderr => self.usage.stderr_bytes += bytes,
+        }
+    }
+
+    /// Records the memory and CPU time of the process tree right now.
+    pub fn sample(&mut self) {
+        let rss: Vec<u64> = process_tree(self.pid).into_iter().filter_map(rss_bytes).collect();
+        if !rss.is_empty() {
+            let rss = rss.iter().sum();
+            self.peak_rss = Some(self.peak_rss.map_or(rss, |peak| peak.max(rss)));
+        }
+        if let Some(cpu) = tree_cpu_times(self.pid) {
+            self.last_cpu = Some(cpu);
+        }
+    }
+
+    /// Usage of the block, measured up to now or up to the last sample if the process is gone.
+    pub fn finish(mut self) -> ResourceUsage {
+        self.sample();
+        if let (Some((start_user, start_system)), Some((user, system))) = (self.start_cpu, self.last_cpu) {
+            self.usage.cpu_user_secs = Some(round_millis(user.saturating_sub(start_user)));
+            self.usage.cpu_system_secs = Some(round_millis(system.saturating_sub(start_system)));
+        }
+        self.usage.peak_rss_bytes = self.peak_rss;
+        self.usage
+    }
+}
+
+fn tree_cpu_times(pid: u32) -> Option<(Duration, Duration)> {
+    let times: Vec<(Duration, Duration)> = process_tree(pid).into_iter().filter_map(cpu_times).collect();
+    if times.is_empty() {
+        return None;
+    }
+    Some(times.into_iter().fold((Duration::ZERO, Duration::ZERO), |(user, system), (u, s)| (user + u, system + s)))
+}
+
+fn round_millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let usage = ResourceUsage {
+            cpu_user_secs: Some(0.95),
+            cpu_system_secs: Some(0.1),
+            peak_rss_bytes: Some(52_300_000),
+            stdout_bytes: 1_100,
+            stderr_bytes: 100,
+        };
+        assert_eq!(usage.summary(Duration::from_millis(1200)), "1.20s wall, 0.95s user, 0.10s sys, 52.3 MB peak, 1.2 kB out");
+        assert_eq!(ResourceUsage::default().summary(Duration::ZERO), "0.00s wall, 0 B out");
+    }
+}
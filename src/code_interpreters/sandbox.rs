@@ -0,0 +1,406 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use tokio::process::Command;
+
+use crate::config::SandboxConfig;
+
+/// Resource limits applied with `setrlimit`, `None` leaves a limit as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// CPU seconds of each process.
+    pub cpu_secs: Option<u64>,
+    /// Bytes of address space of each process.
+    pub memory_bytes: Option<u64>,
+    /// Largest file a process may write.
+    pub file_size_bytes: Option<u64>,
+    /// Processes and threads of the user.
+    pub processes: Option<u64>,
+}
+
+/// Environment variables pointing HTTP clients at a proxy.
+const PROXY_VARS: &[&str] = &["HTTP_PROXY", "HTTPS_PROXY", "ALL_PROXY", "http_proxy", "https_proxy", "all_proxy"];
+
+/// Network access of the processes in a [`Sandbox`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Network {
+    #[default]
+    Full,
+    /// A network namespace of their own with nothing but loopback.
+    None,
+    /// Through the HTTP(S) proxy at this URL, set in `HTTP_PROXY` and `HTTPS_PROXY`. Programs
+    /// that ignore those variables still reach any host.
+    Proxy(String),
+}
+
+/// Runs interpreter processes shut off from the rest of the machine.
+///
+/// The process gets user and mount namespaces of its own, and a network namespace with nothing
+/// but loopback if `network` is [`Network::None`]. Landlock makes the filesystem read-only except
+/// for the workspace, the scratch directories and `writable`, and `setrlimit` caps what it may use.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    pub workspace: PathBuf,
+    /// Directories code may write to besides the workspace and the scratch directories.
+    pub writable: Vec<PathBuf>,
+    pub limits: Limits,
+    pub network: Network,
+    /// Landlock ABI version of the kernel, `None` leaves the filesystem alone.
+    landlock: Option<i64>,
+}
+
+impl Sandbox {
+    /// A sandbox writable in `workspace` only, with network access and no limits.
+    ///
+    /// Fails if the kernel doesn't support Landlock, so code never runs less isolated than asked.
+    pub fn new(workspace: impl Into<PathBuf>) -> Result<Self> {
+        let abi = landlock_abi()?;
+        Ok(Sandbox {
+            workspace: workspace.into(),
+            writable: Vec::new(),
+            limits: Limits::default(),
+            network: Network::Full,
+            landlock: Some(abi),
+        })
+    }
+
+    /// A sandbox that only restricts the network, the filesystem and limits are left alone.
+    ///
+    /// Fails for [`Network::None`] without network namespaces, so code never gets the network it
+    /// was told it doesn't have.
+    pub fn network_only(network: Network) -> Result<Self> {
+        if network == Network::None && !cfg!(target_os = "linux") {
+            bail!("turning off the network needs network namespaces, which only Linux has");
+        }
+        Ok(Sandbox { workspace: PathBuf::new(), writable: Vec::new(), limits: Limits::default(), network, landlock: None })
+    }
+
+    /// Sandbox set up by the `[sandbox]` table of the config file.
+    pub fn from_config(config: &SandboxConfig, workspace: impl Into<PathBuf>) -> Result<Self> {
+        const MIB: u64 = 1 << 20;
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let mut sandbox = Sandbox::new(workspace)?;
+        sandbox.writable = config
+            .writable
+            .iter()
+            .map(|path| match (path.strip_prefix("~"), &home) {
+                (Ok(rest), Some(home)) => home.join(rest),
+                _ => path.clone(),
+            })
+            .collect();
+        sandbox.limits = Limits {
+            cpu_secs: config.cpu_time,
+            memory_bytes: config.memory.map(|memory| memory * MIB),
+            file_size_bytes: config.file_size.map(|file_size| file_size * MIB),
+            processes: config.processes,
+        };
+        Ok(sandbox)
+    }
+
+    /// Directories writable in the sandbox: the workspace, the scratch directories and `writable`.
+    pub fn writable_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.workspace.clone(), PathBuf::from("/tmp"), PathBuf::from("/var/tmp")];
+        dirs.extend(env::var_os("TMPDIR").map(PathBuf::from));
+        dirs.extend(self.writable.iter().cloned());
+        dirs
+    }
+
+    /// Makes `cmd` start its process in the sandbox.
+    pub fn apply(&self, cmd: &mut Command) {
+        if let Network::Proxy(url) = &self.network {
+            for name in PROXY_VARS {
+                cmd.env(name, url);
+            }
+            cmd.env_remove("NO_PROXY").env_remove("no_proxy");
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let setup = linux::Setup::new(self);
+            if !setup.is_empty() {
+                // SAFETY: `enter` only makes syscalls on data prepared before forking.
+                unsafe {
+                    cmd.pre_exec(move || setup.enter());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn landlock_abi() -> Result<i64> {
+    // SAFETY: asking for the ABI version passes no memory to the kernel.
+    let abi = unsafe {
+        libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<u8>(), 0usize, linux::CREATE_RULESET_VERSION)
+    };
+    if abi < 1 {
+        bail!("the sandbox needs Landlock, which this kernel doesn't support: {}", std::io::Error::last_os_error());
+    }
+    Ok(abi)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn landlock_abi() -> Result<i64> {
+    bail!("the sandbox is only available on Linux")
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CString;
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::ptr;
+
+    use super::{Limits, Network, Path, Sandbox};
+
+    pub const CREATE_RULESET_VERSION: u32 = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    // Access rights from `linux/landlock.h`.
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    /// Every right of ABI 1, from `EXECUTE` up to `MAKE_SYM`.
+    const ACCESS_FS_ABI_1: u64 = (1 << 13) - 1;
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    /// Rights that make sense on a file, the others only apply to directories.
+    const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+    const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+    #[cfg(target_env = "gnu")]
+    type Resource = libc::__rlimit_resource_t;
+    #[cfg(not(target_env = "gnu"))]
+    type Resource = libc::c_int;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Everything the child needs, prepared before forking since it must not allocate after.
+    pub struct Setup {
+        clone_flags: libc::c_int,
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+        limits: Limits,
+        /// Access rights Landlock restricts, 0 leaves the filesystem alone.
+        handled_access: u64,
+        /// Paths and the access granted below them.
+        rules: Vec<(CString, u64)>,
+    }
+
+    impl Setup {
+        pub fn new(sandbox: &Sandbox) -> Self {
+            let mut clone_flags = 0;
+            if sandbox.landlock.is_some() {
+                clone_flags |= libc::CLONE_NEWUSER | libc::CLONE_NEWNS;
+            }
+            if sandbox.network == Network::None {
+                // Creating a network namespace without privileges needs a user namespace.
+                clone_flags |= libc::CLONE_NEWUSER | libc::CLONE_NEWNET;
+            }
+
+            let mut handled_access = 0;
+            let mut rules: Vec<(CString, u64)> = Vec::new();
+            if let Some(abi) = sandbox.landlock {
+                handled_access = ACCESS_FS_ABI_1;
+                if abi >= 2 {
+                    handled_access |= ACCESS_FS_REFER;
+                }
+                if abi >= 3 {
+                    handled_access |= ACCESS_FS_TRUNCATE;
+                }
+                let path = |path: &Path| CString::new(path.as_os_str().as_bytes()).ok();
+                rules.extend(path(Path::new("/")).map(|root| (root, ACCESS_READ)));
+                // `/dev/null` and the terminal are written to by nearly everything.
+                rules.extend(path(Path::new("/dev")).map(|dev| (dev, ACCESS_READ | ACCESS_FS_WRITE_FILE)));
+                for dir in sandbox.writable_dirs() {
+                    let access = if dir.is_dir() { handled_access } else { handled_access & ACCESS_FILE };
+                    rules.extend(path(&dir).map(|dir| (dir, access)));
+                }
+            }
+
+            // SAFETY: getuid and getgid can't fail.
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            Setup {
+                clone_flags,
+                // Keep our ids inside, so files keep their owners.
+                uid_map: format!("{uid} {uid} 1").into_bytes(),
+                gid_map: format!("{gid} {gid} 1").into_bytes(),
+                limits: sandbox.limits,
+                handled_access,
+                rules,
+            }
+        }
+
+        /// Whether there is nothing to set up in the child.
+        pub fn is_empty(&self) -> bool {
+            self.clone_flags == 0 && self.handled_access == 0 && self.limits == Limits::default()
+        }
+
+        /// Moves the calling process into the sandbox, run in the child between `fork` and `exec`.
+        pub fn enter(&self) -> io::Result<()> {
+            // SAFETY: only syscalls on memory owned by `self`, which outlives them.
+            unsafe {
+                if self.clone_flags != 0 {
+                    check(libc::unshare(self.clone_flags))?;
+                    write_file(b"/proc/self/setgroups\0", b"deny")?;
+                    write_file(b"/proc/self/uid_map\0", &self.uid_map)?;
+                    write_file(b"/proc/self/gid_map\0", &self.gid_map)?;
+                }
+                if self.clone_flags & libc::CLONE_NEWNS != 0 {
+                    // Mounts made inside must not show up outside.
+                    let root = b"/\0".as_ptr().cast();
+                    check(libc::mount(ptr::null(), root, ptr::null(), libc::MS_REC | libc::MS_PRIVATE, ptr::null()))?;
+                }
+                if self.clone_flags & libc::CLONE_NEWNET != 0 {
+                    bring_up_loopback();
+                }
+
+                set_limit(libc::RLIMIT_CPU, self.limits.cpu_secs)?;
+                set_limit(libc::RLIMIT_AS, self.limits.memory_bytes)?;
+                set_limit(libc::RLIMIT_FSIZE, self.limits.file_size_bytes)?;
+                set_limit(libc::RLIMIT_NPROC, self.limits.processes)?;
+                if self.handled_access != 0 {
+                    self.restrict_filesystem()?;
+                }
+                Ok(())
+            }
+        }
+
+        unsafe fn restrict_filesystem(&self) -> io::Result<()> {
+            let attr = RulesetAttr { handled_access_fs: self.handled_access };
+            let ruleset = libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                mem::size_of::<RulesetAttr>(),
+                0u32,
+            );
+            check(ruleset)?;
+            let ruleset = ruleset as libc::c_int;
+            for (path, access) in &self.rules {
+                let fd = libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC);
+                // Directories that don't exist need no rule.
+                if fd < 0 {
+                    continue;
+                }
+                let rule = PathBeneathAttr { allowed_access: access & self.handled_access, parent_fd: fd };
+                let added =
+                    libc::syscall(libc::SYS_landlock_add_rule, ruleset, RULE_PATH_BENEATH, &rule as *const PathBeneathAttr, 0u32);
+                libc::close(fd);
+                check(added)?;
+            }
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            let restricted = libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0u32);
+            libc::close(ruleset);
+            check(restricted)?;
+            Ok(())
+        }
+    }
+
+    fn check<T: Into<i64>>(ret: T) -> io::Result<()> {
+        if ret.into() < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes `contents` to the file at the nul-terminated `path`.
+    unsafe fn write_file(path: &[u8], contents: &[u8]) -> io::Result<()> {
+        let fd = libc::open(path.as_ptr().cast(), libc::O_WRONLY | libc::O_CLOEXEC);
+        check(fd)?;
+        let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+        libc::close(fd);
+        check(written as i64)
+    }
+
+    unsafe fn set_limit(resource: Resource, limit: Option<u64>) -> io::Result<()> {
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+        let rlimit = libc::rlimit { rlim_cur: limit as libc::rlim_t, rlim_max: limit as libc::rlim_t };
+        check(libc::setrlimit(resource, &rlimit))
+    }
+
+    /// A new network namespace starts with `lo` down, local servers need it up.
+    unsafe fn bring_up_loopback() {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return;
+        }
+        let mut ifreq: libc::ifreq = mem::zeroed();
+        for (dst, src) in ifreq.ifr_name.iter_mut().zip(b"lo\0") {
+            *dst = *src as libc::c_char;
+        }
+        ifreq.ifr_ifru.ifru_flags = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+        libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &ifreq);
+        libc::close(fd);
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sandbox_is_read_only_outside_the_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        let Ok(mut sandbox) = Sandbox::new(workspace.path()) else {
+            eprintln!("skipped, Landlock is not available");
+            return;
+        };
+        sandbox.network = Network::None;
+        // A directory we own outside the writable ones, so only the sandbox can stop the write.
+        let home = env::var_os("HOME").map(PathBuf::from).filter(|home| !sandbox.writable_dirs().iter().any(|dir| home.starts_with(dir)));
+        let Some(outside) = home.and_then(|home| tempfile::Builder::new().prefix(".code-interpreter-sandbox-test").tempdir_in(home).ok())
+        else {
+            eprintln!("skipped, no writable directory outside the sandbox");
+            return;
+        };
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("echo hi > inside && ! touch \"$1/outside\" 2>/dev/null && test \"$(grep -c : /proc/net/dev)\" = 1")
+            .arg("sh")
+            .arg(outside.path())
+            .current_dir(workspace.path());
+        sandbox.apply(&mut cmd);
+        let output = match cmd.output().await {
+            Ok(output) => output,
+            // Unprivileged user namespaces may be turned off.
+            Err(err) => {
+                eprintln!("skipped, the sandbox can't start: {err}");
+                return;
+            }
+        };
+        assert!(!outside.path().join("outside").exists(), "wrote outside the sandbox");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(workspace.path().join("inside").exists());
+    }
+
+    #[tokio::test]
+    async fn test_network_only_sandbox() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("test \"$(grep -c : /proc/net/dev)\" = 1");
+        Sandbox::network_only(Network::None).unwrap().apply(&mut cmd);
+        let Ok(output) = cmd.output().await else {
+            eprintln!("skipped, user namespaces are turned off");
+            return;
+        };
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("echo $HTTPS_PROXY");
+        Sandbox::network_only(Network::Proxy("http://127.0.0.1:3128".to_string())).unwrap().apply(&mut cmd);
+        assert_eq!(cmd.output().await.unwrap().stdout, b"http://127.0.0.1:3128\n");
+    }
+}
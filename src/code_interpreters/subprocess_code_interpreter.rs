@@ -0,0 +1,377 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_stream::try_stream;
+use futures::stream::{BoxStream, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+
+use crate::code_interpreters::binary_output::LineDecoder;
+use crate::code_interpreters::pty::{InputPrompts, PromptSource};
+use crate::code_interpreters::{OutputChunk, OutputStream, Sandbox};
+use crate::utils::process_tree::process_cwd;
+
+/// Marker printed by preprocessed code once a block has finished running.
+pub const END_OF_EXECUTION: &str = "##end_of_execution##";
+
+/// Marker printed by preprocessed code before each line is executed.
+pub const ACTIVE_LINE: &str = "##active_line";
+
+/// Interrupts the code running in an interpreter without borrowing the interpreter.
+pub type Interrupter = Box<dyn Fn() -> Result<()> + Send + Sync>;
+
+/// A running interpreter process, leading its own process group.
+///
+/// Lines printed on stdout and stderr are collected into one channel in the order they arrive.
+/// Dropping it kills the whole process group.
+pub struct Subprocess {
+    child: Child,
+    pid: u32,
+    stdin: ChildStdin,
+    lines: mpsc::UnboundedReceiver<OutputChunk>,
+    exit_code: Option<i32>,
+    /// Prompts on the terminal of a process started with [`spawn_pty`](Subprocess::spawn_pty).
+    prompts: Option<PromptSource>,
+}
+
+impl Subprocess {
+    pub fn spawn(mut cmd: Command) -> Result<Self> {
+        // A group of its own keeps terminal signals away and lets us kill everything it started.
+        #[cfg(unix)]
+        cmd.process_group(0);
+        let mut child =
+            cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true).spawn()?;
+        let pid = child.id().ok_or_else(|| anyhow!("process exited right after spawning"))?;
+
+        let (tx, lines) = mpsc::unbounded_channel();
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_lines(stdout, OutputStream::Stdout, tx.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_lines(stderr, OutputStream::Stderr, tx));
+        }
+        let stdin = child.stdin.take().ok_or_else(|| anyhow!("stdin of the process is not captured"))?;
+
+        Ok(Subprocess { child, pid, stdin, lines, exit_code: None, prompts: None })
+    }
+
+    /// Like [`spawn`](Subprocess::spawn), with stdout and stderr on a pseudo-terminal for
+    /// programs that behave differently without one. Both go to the terminal like on a screen, so
+    /// all output counts as stdout.
+    ///
+    /// Code is still written to a pipe, a shell reading it from a terminal would think it's
+    /// interactive. Blocks read their input from the pty as `/dev/tty`, which is the controlling
+    /// terminal of the process, leading a session and process group of its own.
+    #[cfg(unix)]
+    pub fn spawn_pty(mut cmd: Command) -> Result<Self> {
+        use crate::code_interpreters::pty::{self, Pty};
+
+        let Pty { master, slave } = Pty::open()?;
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave))
+            .kill_on_drop(true);
+        // SAFETY: the hook only makes async-signal-safe calls.
+        unsafe {
+            cmd.pre_exec(pty::set_controlling_terminal);
+        }
+        let mut child = cmd.spawn()?;
+        // The parent's copies of the slave end must go, or reading the master never ends.
+        drop(cmd);
+        let pid = child.id().ok_or_else(|| anyhow!("process exited right after spawning"))?;
+
+        let (tx, lines) = mpsc::unbounded_channel();
+        let prompts = PromptSource::new(master.try_clone()?);
+        std::thread::Builder::new()
+            .name("pty-reader".to_string())
+            .spawn({
+                let prompts = prompts.clone();
+                move || pty::read_terminal(master, tx, prompts)
+            })?;
+        let stdin = child.stdin.take().ok_or_else(|| anyhow!("stdin of the process is not captured"))?;
+
+        Ok(Subprocess { child, pid, stdin, lines, exit_code: None, prompts: Some(prompts) })
+    }
+
+    /// Writes `code` followed by a newline to the process' stdin, starting a new block.
+    pub async fn write(&mut self, code: &str) -> Result<()> {
+        self.exit_code = None;
+        self.stdin.write_all(code.as_bytes()).await?;
+        self.stdin.write_all(b"\n").await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Next line of output without its line ending, `None` once the process closed both stdout
+    /// and stderr.
+    pub async fn next_line(&mut self) -> Option<OutputChunk> {
+        self.lines.recv().await
+    }
+
+    /// Pid of the process, which is also the id of its process group.
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Prompts for input from now on, `None` without a terminal to detect them on.
+    pub fn input_prompts(&self) -> Option<InputPrompts> {
+        self.prompts.as_ref().map(PromptSource::subscribe)
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Exit status of the last block, or of the process itself once it has exited.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn set_exit_code(&mut self, exit_code: Option<i32>) {
+        self.exit_code = exit_code;
+    }
+
+    /// Waits briefly for the process to exit after it closed its output and records its status.
+    async fn reap(&mut self) {
+        if let Ok(Ok(status)) = tokio::time::timeout(Duration::from_secs(1), self.child.wait()).await {
+            self.exit_code = status.code();
+        }
+    }
+
+    /// Kills the process and everything it started.
+    pub fn kill(&mut self) {
+        #[cfg(unix)]
+        let _ = signal_process_group(self.pid, libc::SIGKILL);
+        let _ = self.child.start_kill();
+    }
+}
+
+impl Drop for Subprocess {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+#[cfg(unix)]
+pub fn signal_process_group(pgid: u32, signal: libc::c_int) -> Result<()> {
+    // SAFETY: killpg only sends a signal, a stale group id at worst fails with ESRCH.
+    if unsafe { libc::killpg(pgid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error().into())
+    }
+}
+
+#[cfg(unix)]
+pub fn signal_process(pid: u32, signal: libc::c_int) -> Result<()> {
+    // SAFETY: kill only sends a signal, a stale pid at worst fails with ESRCH.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error().into())
+    }
+}
+
+/// Sends the lines of `reader` to `tx`, see [`LineDecoder`] and [`decode_line`].
+async fn forward_lines<R: AsyncRead + Unpin>(reader: R, stream: OutputStream, tx: mpsc::UnboundedSender<OutputChunk>) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    let mut decoder = LineDecoder::default();
+    loop {
+        line.clear();
+        if !matches!(reader.read_until(b'\n', &mut line).await, Ok(read) if read > 0) {
+            break;
+        }
+        let newline = line.last() == Some(&b'\n');
+        if newline {
+            line.pop();
+        }
+        for content in decode_line(&mut decoder, &line, newline) {
+            if tx.send(OutputChunk { stream, content }).is_err() {
+                return;
+            }
+        }
+    }
+    for content in decoder.flush() {
+        let _ = tx.send(OutputChunk { stream, content });
+    }
+}
+
+/// Lines to send for `line`, which ended in a newline if `newline`.
+///
+/// Output printed without a final newline is split from the end of execution marker it shares
+/// its line with.
+pub(crate) fn decode_line(decoder: &mut LineDecoder, line: &[u8], newline: bool) -> Vec<String> {
+    match find(line, END_OF_EXECUTION.as_bytes()) {
+        Some(start) => {
+            let mut lines = if start > 0 { decoder.push(&line[..start], false) } else { Vec::new() };
+            lines.extend(decoder.flush());
+            lines.push(String::from_utf8_lossy(&line[start..]).into_owned());
+            lines
+        }
+        None => decoder.push(line, newline),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// A language backend that executes code in a child process.
+///
+/// Implementors describe how to launch the process and how to massage code and output,
+/// the provided methods take care of spawning, feeding and reaping it.
+pub trait SubprocessCodeInterpreter: Send {
+    /// Name of the language in the `execute` function schema, e.g. `shell`.
+    fn language(&self) -> &'static str;
+
+    /// Human readable name of the language.
+    fn proper_name(&self) -> &'static str;
+
+    /// File extension used to highlight code of this language.
+    fn file_extension(&self) -> &'static str;
+
+    /// Command launching the process; preprocessed code is written to its stdin.
+    fn start_cmd(&self) -> Command;
+
+    /// Slot holding the running process.
+    fn process(&mut self) -> &mut Option<Subprocess>;
+
+    /// Starts the process in `sandbox` from now on, `false` if this backend can't be sandboxed.
+    fn set_sandbox(&mut self, _sandbox: Sandbox) -> bool {
+        false
+    }
+
+    fn preprocess_code(&self, code: &str) -> String {
+        code.to_string()
+    }
+
+    /// Rewrites a line of output, `None` drops it.
+    fn line_postprocessor(&self, line: &str) -> Option<String> {
+        Some(line.to_string())
+    }
+
+    fn detect_active_line(&self, line: &str) -> Option<usize> {
+        if line.contains(ACTIVE_LINE) {
+            let active_line_str = line.split(ACTIVE_LINE).nth(1)?.split("##").next()?;
+            active_line_str.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    fn detect_end_of_execution(&self, line: &str) -> bool {
+        line.contains(END_OF_EXECUTION)
+    }
+
+    /// Exit status printed after the end of execution marker, e.g. `##end_of_execution##1`.
+    fn detect_exit_code(&self, line: &str) -> Option<i32> {
+        line.split(END_OF_EXECUTION).nth(1)?.trim().parse().ok()
+    }
+
+    /// Exit status of the last block run, `None` if it is unknown.
+    fn exit_code(&mut self) -> Option<i32> {
+        self.process().as_ref()?.exit_code()
+    }
+
+    /// Prompts for input the running code stops at from now on, `None` if this backend can't
+    /// tell.
+    fn input_prompts(&mut self) -> Option<InputPrompts> {
+        self.process().as_ref()?.input_prompts()
+    }
+
+    /// Pid of the running process.
+    fn pid(&mut self) -> Option<u32> {
+        Some(self.process().as_ref()?.id())
+    }
+
+    /// Working directory of the running process, which code may have changed.
+    fn cwd(&mut self) -> Option<PathBuf> {
+        process_cwd(self.pid()?)
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.process().as_mut().is_some_and(Subprocess::is_running)
+    }
+
+    /// Spawns the process from [`start_cmd`](SubprocessCodeInterpreter::start_cmd).
+    fn spawn(&self) -> Result<Subprocess> {
+        Subprocess::spawn(self.start_cmd())
+    }
+
+    fn start_process(&mut self) -> Result<()> {
+        self.terminate();
+        *self.process() = Some(self.spawn()?);
+        Ok(())
+    }
+
+    /// Runs `code` in the long-lived process, starting it first if needed, and streams its
+    /// output line by line as it is printed.
+    ///
+    /// The preprocessed code must print [`END_OF_EXECUTION`] on both stdout and stderr once it
+    /// is done, so state like variables or the working directory carries over to the next block.
+    fn run<'a>(&'a mut self, code: &'a str) -> BoxStream<'a, Result<OutputChunk>> {
+        try_stream! {
+            let code = self.preprocess_code(code);
+            if !self.is_running() {
+                self.start_process()?;
+            }
+            let proper_name = self.proper_name();
+            let process = self.process().as_mut().ok_or_else(|| anyhow!("{proper_name} process is not running"))?;
+            process.write(&code).await?;
+
+            let mut ended_streams = 0;
+            while ended_streams < 2 {
+                let line = match self.process().as_mut() {
+                    Some(process) => process.next_line().await,
+                    None => None,
+                };
+                let Some(OutputChunk { stream, content }) = line else {
+                    // The code exited the process, it is restarted on the next run.
+                    if let Some(process) = self.process().as_mut() {
+                        process.reap().await;
+                    }
+                    break;
+                };
+                if self.detect_end_of_execution(&content) {
+                    if let Some(exit_code) = self.detect_exit_code(&content) {
+                        if let Some(process) = self.process().as_mut() {
+                            process.set_exit_code(Some(exit_code));
+                        }
+                    }
+                    ended_streams += 1;
+                    continue;
+                }
+                if self.detect_active_line(&content).is_some() {
+                    continue;
+                }
+                if let Some(content) = self.line_postprocessor(&content) {
+                    yield OutputChunk { stream, content: content + "\n" };
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Handle interrupting the running code like Ctrl-C would, `None` if nothing is running.
+    ///
+    /// Sends `SIGINT` to the whole process group by default.
+    fn interrupter(&mut self) -> Option<Interrupter> {
+        #[cfg(unix)]
+        {
+            let pgid = self.process().as_ref()?.id();
+            Some(Box::new(move || signal_process_group(pgid, libc::SIGINT)))
+        }
+        #[cfg(not(unix))]
+        None
+    }
+
+    fn terminate(&mut self) {
+        if let Some(mut process) = self.process().take() {
+            process.kill();
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use crate::config::Config;
+
+/// Timeout used when neither `timeout` nor a timeout for the language is set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How long a block of code may run before its process group is killed, per language.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    default: Option<Duration>,
+    per_language: HashMap<String, Option<Duration>>,
+}
+
+impl Timeouts {
+    /// Timeouts without any limit.
+    pub fn unlimited() -> Self {
+        Timeouts { default: None, per_language: HashMap::new() }
+    }
+
+    /// The `timeout` and `language_timeouts` of `config`, in seconds. `0` disables the timeout.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut timeouts = Timeouts::default();
+        if let Some(secs) = config.timeout {
+            timeouts.set_default(to_timeout(secs)?);
+        }
+        for (language, &secs) in config.language_timeouts.iter().flatten() {
+            timeouts.set(language.as_str(), to_timeout(secs)?);
+        }
+        Ok(timeouts)
+    }
+
+    pub fn set_default(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.default = timeout;
+        self
+    }
+
+    pub fn set(&mut self, language: impl Into<String>, timeout: Option<Duration>) -> &mut Self {
+        self.per_language.insert(language.into().to_lowercase(), timeout);
+        self
+    }
+
+    pub fn for_language(&self, language: &str) -> Option<Duration> {
+        self.per_language.get(&language.to_lowercase()).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts { default: Some(DEFAULT_TIMEOUT), per_language: HashMap::new() }
+    }
+}
+
+/// `None` for `0`.
+fn to_timeout(secs: f64) -> Result<Option<Duration>> {
+    if !secs.is_finite() || secs < 0.0 {
+        bail!("invalid timeout of {secs} seconds, use 0 for no limit");
+    }
+    Ok((secs > 0.0).then(|| Duration::from_secs_f64(secs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_language_overrides_default() {
+        let mut timeouts = Timeouts::unlimited();
+        timeouts.set_default(Some(Duration::from_secs(5))).set("Python", None);
+        assert_eq!(timeouts.for_language("shell"), Some(Duration::from_secs(5)));
+        assert_eq!(timeouts.for_language("python"), None);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Config {
+            timeout: Some(1.5),
+            language_timeouts: Some(HashMap::from([("Python".to_string(), 0.0)])),
+            ..Config::default()
+        };
+        let timeouts = Timeouts::from_config(&config).unwrap();
+        assert_eq!(timeouts.for_language("shell"), Some(Duration::from_millis(1500)));
+        assert_eq!(timeouts.for_language("python"), None);
+        assert_eq!(Timeouts::from_config(&Config::default()).unwrap().for_language("shell"), Some(DEFAULT_TIMEOUT));
+        assert!(Timeouts::from_config(&Config { timeout: Some(-1.0), ..Config::default() }).is_err());
+    }
+}
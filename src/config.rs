@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+/// How code written by the model is checked before it runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SafeMode {
+    /// Run code without scanning it.
+    #[default]
+    Off,
+    /// Ask the user before running risky code.
+    Ask,
+    /// Refuse risky code and tell the model why.
+    Auto,
+}
+
+impl FromStr for SafeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(SafeMode::Off),
+            "ask" => Ok(SafeMode::Ask),
+            "auto" => Ok(SafeMode::Auto),
+            _ => Err(anyhow!("invalid safe mode `{s}`, use one of off, ask and auto")),
+        }
+    }
+}
+
+impl fmt::Display for SafeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafeMode::Off => write!(f, "off"),
+            SafeMode::Ask => write!(f, "ask"),
+            SafeMode::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// What network code may reach.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    #[default]
+    Full,
+    /// No network at all, code runs in a network namespace of its own.
+    None,
+    /// Only the `allowed_hosts`, through a local HTTP(S) proxy.
+    Allowlist,
+}
+
+impl FromStr for NetworkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "full" => Ok(NetworkMode::Full),
+            "none" => Ok(NetworkMode::None),
+            "allowlist" => Ok(NetworkMode::Allowlist),
+            _ => Err(anyhow!("invalid network mode `{s}`, use one of full, none and allowlist")),
+        }
+    }
+}
+
+impl fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkMode::Full => write!(f, "full"),
+            NetworkMode::None => write!(f, "none"),
+            NetworkMode::Allowlist => write!(f, "allowlist"),
+        }
+    }
+}
+
+/// The `[sandbox]` table of the config file, see [`Sandbox`](crate::code_interpreters::Sandbox).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxConfig {
+    /// Languages whose code runs in the sandbox.
+    pub languages: Vec<String>,
+    /// Directories code may write to besides the working directory and the scratch directories.
+    pub writable: Vec<PathBuf>,
+    /// CPU seconds of each process.
+    pub cpu_time: Option<u64>,
+    /// MiB of memory of each process.
+    pub memory: Option<u64>,
+    /// MiB of the largest file a process may write.
+    pub file_size: Option<u64>,
+    /// Most processes of the user.
+    pub processes: Option<u64>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            languages: vec!["python".to_string(), "shell".to_string()],
+            writable: Vec::new(),
+            cpu_time: None,
+            memory: None,
+            file_size: None,
+            processes: None,
+        }
+    }
+}
+
+/// Settings from the command line, the config file and the environment.
+///
+/// Every field is optional so the sources can be layered with [`Config::merge`], unset fields
+/// fall back to the defaults of [`Session`](crate::core::Session).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub system_message: Option<String>,
+    pub local: Option<bool>,
+    pub auto_run: Option<bool>,
+    pub debug_mode: Option<bool>,
+    pub disable_procedures: Option<bool>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub context_window: Option<u32>,
+    pub max_tokens: Option<u32>,
+    pub max_output: Option<usize>,
+    /// Like `max_output` in tokens, the smaller of both applies.
+    pub max_output_tokens: Option<u32>,
+    /// Add the resources each execution used to its function message.
+    pub report_resources: Option<bool>,
+    /// Seconds a block may run before it is killed, `0` for no limit.
+    pub timeout: Option<f64>,
+    /// Like `timeout` for some languages, by name.
+    pub language_timeouts: Option<HashMap<String, f64>>,
+    pub max_budget: Option<f64>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    pub safe_mode: Option<SafeMode>,
+    pub vision: Option<bool>,
+    /// Run shell commands on a pseudo-terminal and ask the user to answer their prompts.
+    pub pty: Option<bool>,
+    /// Policy of what code may run, see [`Policy::load`](crate::safety::Policy::load).
+    pub policy_file: Option<PathBuf>,
+    pub network: Option<NetworkMode>,
+    /// Hosts code may reach with `network = "allowlist"`, e.g. `pypi.org`.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Only read from the config file.
+    pub sandbox: Option<SandboxConfig>,
+    /// Not read from the config file itself, only from the command line.
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+}
+
+impl Config {
+    /// Settings taken from `OPENAI_API_KEY`, `OPENAI_BASE_URL` and `OPENAI_MODEL`, or their
+    /// unprefixed variants, and the timeouts from `CODE_INTERPRETER_TIMEOUT` and
+    /// `CODE_INTERPRETER_TIMEOUT_<LANGUAGE>`.
+    pub fn from_env() -> Self {
+        let var = |name: &str, fallback: &str| env::var(name).or_else(|_| env::var(fallback)).ok();
+        let language_timeouts: HashMap<String, f64> = env::vars()
+            .filter_map(|(key, value)| {
+                let language = key.strip_prefix("CODE_INTERPRETER_TIMEOUT_")?;
+                Some((language.to_lowercase(), value.trim().parse().ok()?))
+            })
+            .collect();
+        Config {
+            api_key: var("OPENAI_API_KEY", "API_KEY"),
+            api_base: var("OPENAI_BASE_URL", "BASE_URL"),
+            model: var("OPENAI_MODEL", "MODEL"),
+            timeout: env::var("CODE_INTERPRETER_TIMEOUT").ok().and_then(|secs| secs.trim().parse().ok()),
+            language_timeouts: Some(language_timeouts).filter(|timeouts| !timeouts.is_empty()),
+            ..Config::default()
+        }
+    }
+
+    /// Reads a TOML config file, keys are named like the long command line flags.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// `config.toml` in `$XDG_CONFIG_HOME/code-interpreter` or `~/.config/code-interpreter`.
+    pub fn default_file() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("code-interpreter").join("config.toml"))
+    }
+
+    /// Settings from the command line, then the config file, then the environment.
+    ///
+    /// The config file is `--config_file`, `CODE_INTERPRETER_CONFIG` or the default file if it exists.
+    pub fn load(cli: Config) -> Result<Self> {
+        let config_file = match cli.config_file.clone().or_else(|| env::var_os("CODE_INTERPRETER_CONFIG").map(PathBuf::from)) {
+            Some(path) => Some(path),
+            None => Config::default_file().filter(|path| path.is_file()),
+        };
+        let file = match &config_file {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        let mut config = cli.merge(file).merge(Config::from_env());
+        config.config_file = config_file;
+        Ok(config)
+    }
+
+    /// Fills the fields unset in `self` from `other`, `language_timeouts` language by language.
+    pub fn merge(self, other: Config) -> Self {
+        let language_timeouts = match (self.language_timeouts, other.language_timeouts) {
+            (Some(mine), Some(mut theirs)) => {
+                theirs.extend(mine);
+                Some(theirs)
+            }
+            (mine, theirs) => mine.or(theirs),
+        };
+        Config {
+            system_message: self.system_message.or(other.system_message),
+            local: self.local.or(other.local),
+            auto_run: self.auto_run.or(other.auto_run),
+            debug_mode: self.debug_mode.or(other.debug_mode),
+            disable_procedures: self.disable_procedures.or(other.disable_procedures),
+            model: self.model.or(other.model),
+            temperature: self.temperature.or(other.temperature),
+            context_window: self.context_window.or(other.context_window),
+            max_tokens: self.max_tokens.or(other.max_tokens),
+            max_output: self.max_output.or(other.max_output),
+            max_output_tokens: self.max_output_tokens.or(other.max_output_tokens),
+            report_resources: self.report_resources.or(other.report_resources),
+            timeout: self.timeout.or(other.timeout),
+            language_timeouts,
+            max_budget: self.max_budget.or(other.max_budget),
+            api_base: self.api_base.or(other.api_base),
+            api_key: self.api_key.or(other.api_key),
+            safe_mode: self.safe_mode.or(other.safe_mode),
+            vision: self.vision.or(other.vision),
+            pty: self.pty.or(other.pty),
+            policy_file: self.policy_file.or(other.policy_file),
+            network: self.network.or(other.network),
+            allowed_hosts: self.allowed_hosts.or(other.allowed_hosts),
+            sandbox: self.sandbox.or(other.sandbox),
+            config_file: self.config_file.or(other.config_file),
+        }
+    }
+
+    pub fn auto_run(&self) -> bool {
+        self.auto_run.unwrap_or(false)
+    }
+
+    pub fn debug_mode(&self) -> bool {
+        self.debug_mode.unwrap_or(false)
+    }
+
+    pub fn local(&self) -> bool {
+        self.local.unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_prefers_self() {
+        let cli = Config { model: Some("gpt-4o".to_string()), auto_run: Some(true), ..Config::default() };
+        let file = Config { model: Some("gpt-4".to_string()), max_tokens: Some(1024), ..Config::default() };
+        let config = cli.merge(file);
+        assert_eq!(config.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(config.max_tokens, Some(1024));
+        assert!(config.auto_run());
+        assert!(!config.local());
+
+        let timeouts = |timeouts: &[(&str, f64)]| -> Config {
+            let timeouts = timeouts.iter().map(|&(language, secs)| (language.to_string(), secs)).collect();
+            Config { language_timeouts: Some(timeouts), ..Config::default() }
+        };
+        let config = timeouts(&[("python", 60.0)]).merge(timeouts(&[("python", 5.0), ("shell", 0.0)]));
+        assert_eq!(config.language_timeouts, timeouts(&[("python", 60.0), ("shell", 0.0)]).language_timeouts);
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let config: Config = toml::from_str(
+            "model = \"gpt-4o\"\nauto_run = true\nsafe_mode = \"ask\"\nmax_budget = 0.5\ntimeout = 60\n[language_timeouts]\npython = 0\n",
+        )
+        .unwrap();
+        assert_eq!(config.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(config.safe_mode, Some(SafeMode::Ask));
+        assert_eq!(config.max_budget, Some(0.5));
+        assert_eq!(config.timeout, Some(60.0));
+        assert_eq!(config.language_timeouts, Some(HashMap::from([("python".to_string(), 0.0)])));
+        assert!(toml::from_str::<Config>("modle = \"gpt-4o\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_sandbox_table() {
+        let config: Config = toml::from_str("[sandbox]\nmemory = 2048\nwritable = [\"~/.cache\"]\n").unwrap();
+        let sandbox = config.sandbox.unwrap();
+        assert_eq!(sandbox.memory, Some(2048));
+        assert_eq!(sandbox.writable, [PathBuf::from("~/.cache")]);
+        assert_eq!(sandbox.languages, ["python", "shell"]);
+
+        let config: Config = toml::from_str("network = \"allowlist\"\nallowed_hosts = [\"pypi.org\"]\n").unwrap();
+        assert_eq!(config.network, Some(NetworkMode::Allowlist));
+        assert_eq!(config.allowed_hosts, Some(vec!["pypi.org".to_string()]));
+    }
+}
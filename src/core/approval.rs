@@ -0,0 +1,160 @@
+use std::fs;
+use std::io::{stdout, IsTerminal, Write};
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use tokio::process::Command;
+
+/// What the user wants done with a block of code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    Run,
+    /// Don't run it, the model is told the user declined.
+    Skip,
+    /// Open it in `$EDITOR` and ask again.
+    Edit,
+    /// Run it and every later block of the same language without asking.
+    AlwaysAllow,
+}
+
+impl Approval {
+    /// Reads an answer like `y`, `no` or `e`, `None` if it is none of them.
+    pub fn parse(answer: &str) -> Option<Self> {
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => Some(Approval::Run),
+            "n" | "no" => Some(Approval::Skip),
+            "e" | "edit" => Some(Approval::Edit),
+            "a" | "always" => Some(Approval::AlwaysAllow),
+            _ => None,
+        }
+    }
+}
+
+/// Asks the user whether to run the block of `language` shown above, until they give a valid answer.
+///
+/// Closing stdin counts as declining.
+pub async fn ask_approval(language: &str) -> Result<Approval> {
+    loop {
+        print!("{}", format!("  Run this code? (y)es / (n)o / (e)dit / (a)lways allow {language}: ").bold());
+        stdout().flush()?;
+        let answer = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map(|read| (read > 0).then_some(line))
+        })
+        .await??;
+        let Some(answer) = answer else {
+            println!();
+            return Ok(Approval::Skip);
+        };
+        if let Some(approval) = Approval::parse(&answer) {
+            return Ok(approval);
+        }
+    }
+}
+
+/// Asks the user for the answer to `prompt`, which the running code waits at. What they type is
+/// hidden if it looks like a password.
+///
+/// `None` stops the code, when the user presses CTRL-D or stdin is not a terminal.
+pub async fn ask_input(prompt: &str) -> Result<Option<String>> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let lowercase = prompt.to_lowercase();
+    let hidden = ["password", "passphrase", "pin", "token", "secret"].iter().any(|word| lowercase.contains(word));
+    println!();
+    print!("{}", format!("  The code waits for input at `{}`, answer or press CTRL-D to stop it: ", prompt.trim()).bold());
+    stdout().flush()?;
+    let answer = tokio::task::spawn_blocking(move || {
+        #[cfg(unix)]
+        let _echo = hidden.then(HiddenInput::start);
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map(|read| (read > 0).then_some(line))
+    })
+    .await??;
+    if hidden || answer.is_none() {
+        println!();
+    }
+    Ok(answer.map(|line| line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Turns off echo on the terminal on stdin until dropped.
+#[cfg(unix)]
+struct HiddenInput(Option<libc::termios>);
+
+#[cfg(unix)]
+impl HiddenInput {
+    fn start() -> Self {
+        let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: tcgetattr fills `termios` when it succeeds, which is checked before it is read.
+        let original = unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return HiddenInput(None);
+            }
+            termios.assume_init()
+        };
+        let mut hidden = original;
+        hidden.c_lflag &= !libc::ECHO;
+        // SAFETY: only changes the settings of the terminal on stdin.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &hidden) };
+        HiddenInput(Some(original))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for HiddenInput {
+    fn drop(&mut self) {
+        if let Some(original) = &self.0 {
+            // SAFETY: restores the settings read in `start`.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+        }
+    }
+}
+
+/// Opens `code` in `$VISUAL` or `$EDITOR`, `vi` if neither is set, and returns what the user saved.
+pub async fn edit_code(code: &str, extension: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    edit_code_with(&editor, code, extension).await
+}
+
+/// Opens `code` in the command `editor` and returns what the user saved.
+pub async fn edit_code_with(editor: &str, code: &str, extension: &str) -> Result<String> {
+    let mut file = tempfile::Builder::new().prefix("code-interpreter-").suffix(&format!(".{extension}")).tempfile()?;
+    file.write_all(code.as_bytes())?;
+    file.flush()?;
+
+    // The editor may come with arguments, like `code --wait`.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(file.path())
+        .status()
+        .await
+        .with_context(|| format!("failed to start the editor `{editor}`"))?;
+    if !status.success() {
+        bail!("the editor `{editor}` exited with {status}");
+    }
+    Ok(fs::read_to_string(file.path())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_approval() {
+        assert_eq!(Approval::parse("y\n"), Some(Approval::Run));
+        assert_eq!(Approval::parse(" No "), Some(Approval::Skip));
+        assert_eq!(Approval::parse("e"), Some(Approval::Edit));
+        assert_eq!(Approval::parse("always"), Some(Approval::AlwaysAllow));
+        assert_eq!(Approval::parse(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_edit_code() {
+        let editor = r#"[ "${1##*.}" = py ] && grep -q world "$1" && printf "print('hello editor')\n" >"#;
+        assert_eq!(edit_code_with(editor, "print('hello world')\n", "py").await.unwrap(), "print('hello editor')\n");
+        assert!(edit_code_with("false", "", "py").await.is_err());
+    }
+}
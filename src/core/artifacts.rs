@@ -0,0 +1,121 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use tempfile::TempDir;
+use tracing::warn;
+
+use crate::code_interpreters::normalize::normalize_line;
+use crate::code_interpreters::OutputChunk;
+
+/// Directory of files a session leaves for the model, like output too long to send it.
+///
+/// It is created on first use and removed when the session ends.
+#[derive(Default)]
+pub struct Artifacts {
+    dir: Option<TempDir>,
+    count: usize,
+}
+
+impl Artifacts {
+    /// Path for a new file, numbered like `003-shell-output.txt`. The file is not created.
+    pub fn next_path(&mut self, name: &str) -> Result<PathBuf> {
+        let dir = match &mut self.dir {
+            Some(dir) => dir,
+            dir => dir.insert(tempfile::Builder::new().prefix("code-interpreter-artifacts-").tempdir()?),
+        };
+        self.count += 1;
+        Ok(dir.path().join(format!("{:03}-{name}", self.count)))
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_ref().map(TempDir::path)
+    }
+}
+
+/// Writes the output of an execution to a file once it grows past `threshold` bytes, holding it
+/// in memory until then so short output never touches the disk.
+///
+/// Lines are saved like the model sees them, without escape sequences and overwritten text.
+pub struct Spill {
+    path: PathBuf,
+    threshold: usize,
+    buffered: String,
+    file: Option<BufWriter<File>>,
+    failed: bool,
+}
+
+impl Spill {
+    pub fn new(path: PathBuf, threshold: usize) -> Self {
+        Spill { path, threshold, buffered: String::new(), file: None, failed: false }
+    }
+
+    pub fn write(&mut self, chunk: &OutputChunk) {
+        if self.failed {
+            return;
+        }
+        let mut content = String::with_capacity(chunk.content.len());
+        for line in chunk.content.split_inclusive('\n') {
+            let text = line.trim_end_matches('\n');
+            content.push_str(&normalize_line(text));
+            content.push_str(&line[text.len()..]);
+        }
+        if self.file.is_none() {
+            self.buffered.push_str(&content);
+            if self.buffered.len() <= self.threshold {
+                return;
+            }
+            let content = std::mem::take(&mut self.buffered);
+            let file = File::create(&self.path).map(BufWriter::new).and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                Ok(file)
+            });
+            match file {
+                Ok(file) => self.file = Some(file),
+                Err(err) => self.fail(err),
+            }
+            return;
+        }
+        if let Some(Err(err)) = self.file.as_mut().map(|file| file.write_all(content.as_bytes())) {
+            self.fail(err);
+        }
+    }
+
+    /// Path of the file holding all of the output, `None` if it stayed under the threshold.
+    pub fn finish(mut self) -> Option<PathBuf> {
+        let mut file = self.file.take()?;
+        if let Err(err) = file.flush() {
+            self.fail(err);
+            return None;
+        }
+        Some(self.path)
+    }
+
+    fn fail(&mut self, err: std::io::Error) {
+        warn!("Failed to save the output to {}: {err}", self.path.display());
+        self.failed = true;
+        self.file = None;
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_past_threshold() {
+        let mut artifacts = Artifacts::default();
+        let mut spill = Spill::new(artifacts.next_path("shell-output.txt").unwrap(), 4);
+        spill.write(&OutputChunk::stdout("abc"));
+        assert!(!artifacts.dir().unwrap().join("001-shell-output.txt").exists());
+        spill.write(&OutputChunk::stderr("\x1b[31mdef\x1b[0m\n"));
+        spill.write(&OutputChunk::stdout("ghi\n"));
+        let path = spill.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abcdef\nghi\n");
+
+        let spill = Spill::new(artifacts.next_path("shell-output.txt").unwrap(), 4);
+        assert_eq!(spill.finish(), None);
+    }
+}
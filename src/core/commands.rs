@@ -0,0 +1,57 @@
+use anyhow::{anyhow, bail, Result};
+
+/// A `%` command typed at the REPL instead of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplCommand {
+    /// `%reset`, starts a new conversation.
+    Reset,
+    /// `%kernels`, lists the kernels of the session.
+    Kernels,
+    /// `%restart <kernel>`, starts a kernel again with a clean state.
+    Restart(String),
+    /// `%shutdown <kernel>`, stops a kernel.
+    Shutdown(String),
+}
+
+impl ReplCommand {
+    /// The command in `line`, `None` if it is a message for the model.
+    pub fn parse(line: &str) -> Option<Result<ReplCommand>> {
+        let command = line.trim().strip_prefix('%')?;
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let argument = words.next().map(String::from);
+        if words.next().is_some() {
+            return Some(Err(anyhow!("`%{name}` takes at most one argument")));
+        }
+        Some(match (name, argument) {
+            ("reset", None) => Ok(ReplCommand::Reset),
+            ("kernels", None) => Ok(ReplCommand::Kernels),
+            ("restart", Some(kernel)) => Ok(ReplCommand::Restart(kernel)),
+            ("shutdown", Some(kernel)) => Ok(ReplCommand::Shutdown(kernel)),
+            ("restart" | "shutdown", None) => usage(name, " <kernel>"),
+            ("reset" | "kernels", Some(_)) => usage(name, ""),
+            _ => Err(anyhow!("unknown command `%{name}`, use %reset, %kernels, %restart or %shutdown")),
+        })
+    }
+}
+
+fn usage(name: &str, arguments: &str) -> Result<ReplCommand> {
+    bail!("usage: %{name}{arguments}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(ReplCommand::parse(" %reset ").unwrap().unwrap(), ReplCommand::Reset);
+        assert_eq!(ReplCommand::parse("%kernels").unwrap().unwrap(), ReplCommand::Kernels);
+        assert_eq!(ReplCommand::parse("%restart scratch").unwrap().unwrap(), ReplCommand::Restart("scratch".to_string()));
+        assert_eq!(ReplCommand::parse("%shutdown r").unwrap().unwrap(), ReplCommand::Shutdown("r".to_string()));
+        assert!(ReplCommand::parse("plot 50% of it").is_none());
+        assert!(ReplCommand::parse("%restart").unwrap().is_err());
+        assert!(ReplCommand::parse("%reset now").unwrap().is_err());
+        assert!(ReplCommand::parse("%magic").unwrap().is_err());
+    }
+}
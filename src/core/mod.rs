@@ -0,0 +1,15 @@
+pub mod approval;
+pub mod artifacts;
+pub mod commands;
+pub mod session;
+pub mod stop_conditions;
+pub mod tool_calls;
+pub mod usage;
+
+pub use approval::Approval;
+pub use artifacts::{Artifacts, Spill};
+pub use commands::ReplCommand;
+pub use session::{KernelStatus, Session};
+pub use stop_conditions::{StopConditions, StopReason};
+pub use tool_calls::ToolCallAccumulator;
+pub use usage::{Pricing, Usage};
@@ -0,0 +1,877 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
+use std::io::{stdout, Write};
+use std::iter;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use async_openai::config::OpenAIConfig;
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
+    ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs, ChatCompletionStreamOptions,
+    ChatCompletionToolArgs, ChatCompletionToolChoiceOption, CreateChatCompletionRequestArgs, FinishReason, FunctionCall,
+    FunctionObjectArgs,
+};
+use async_openai::Client;
+use colored::Colorize;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::code_interpreters::execution_result::MAX_COLLECTED_OUTPUT;
+use crate::code_interpreters::{
+    self, ExecutionResult, LanguageMap, OutputStream, Sandbox, SubprocessCodeInterpreter, Termination, Timeouts,
+};
+use crate::code_interpreters::jupyter::KernelSpec;
+use crate::code_interpreters::languages::jupyter::Jupyter;
+use crate::code_interpreters::languages::shell::Shell;
+use crate::code_interpreters::proxy::{AllowList, Proxy};
+use crate::code_interpreters::sandbox::Network;
+use crate::config::{Config, NetworkMode, SafeMode};
+use crate::core::approval::{ask_approval, ask_input, edit_code};
+use crate::core::{Approval, Artifacts, Pricing, Spill, StopConditions, StopReason, ToolCallAccumulator, Usage};
+use crate::rag::get_relevant_procedures_string::get_relevant_procedures_string;
+use crate::safety::{self, Policy, RiskLevel, Scan};
+use crate::utils::{get_user_info_string, print_highlighted_code};
+
+pub const DEFAULT_MODEL: &str = "Qwen/Qwen3-Coder-480B-A35B-Instruct";
+
+pub const DEFAULT_MAX_TOKENS: u32 = 512;
+
+/// Rough number of characters per token, to turn token limits into characters.
+pub const CHARS_PER_TOKEN: usize = 4;
+
+/// OpenAI compatible server of LM Studio, used by `--local`.
+pub const LOCAL_API_BASE: &str = "http://localhost:1234/v1";
+
+pub const DEFAULT_SYSTEM_MESSAGE: &str = "You are Open Interpreter, a world-class programmer that can complete any goal by executing code.\n\
+First, write a plan. **Always recap the plan between each code block** (you have extreme short-term memory loss, so you need to recap the plan between each message block to retain it).\n\
+When you execute code, it will be executed **on the user\'s machine**. The user has given you **full and complete permission** to execute any code necessary to complete the task.\n\
+If you want to send data between programming languages, save the data to a txt or json.\n\
+You can access the internet. Run **any code** to achieve the goal, and if at first you don\'t succeed, try again and again.\n\
+You can install new packages.\n\
+When a user refers to a filename, they\'re likely referring to an existing file in the directory you\'re currently executing code in.\n\
+Write messages to the user in Markdown.\n\
+In general, try to **make plans** with as few steps as possible. As for actually executing code to carry out that plan, for *stateful* languages (like python, javascript, shell, but NOT for html which starts from 0 every time) **it\'s critical not to try to do everything in one code block.** You should try something, print information about it, then continue from there in tiny, informed steps. You will never get it on the first try, and attempting it in one go will often lead to errors you cant see.\n\
+You are capable of **any** task.";
+
+/// A conversation with the model that lasts for the whole REPL.
+///
+/// It keeps the message history, the interpreter processes started so far and the settings, so
+/// follow-up messages see what happened before and variables or the working directory carry over.
+pub struct Session {
+    client: Client<OpenAIConfig>,
+    pub model: String,
+    /// Instructions the system message starts with, user info and procedures are appended per turn.
+    pub system_message: String,
+    pub temperature: f32,
+    /// Most tokens the model may answer with in one step.
+    pub max_tokens: u32,
+    /// Tokens the model can read, older messages are left out of requests that would not fit.
+    pub context_window: Option<u32>,
+    /// Most characters of output sent to the model per execution, the middle of longer output
+    /// is left out and all of it saved to `artifacts`.
+    pub max_output: Option<usize>,
+    /// Tell the model the time, CPU and memory each execution used, they are always shown and logged.
+    pub report_resources: bool,
+    /// Run code without asking the user first.
+    pub auto_run: bool,
+    /// Languages the user chose to always run without asking.
+    allowed_languages: HashSet<String>,
+    pub safe_mode: SafeMode,
+    /// What code may run at all, checked before the user is asked.
+    pub policy: Policy,
+    /// Skip looking up procedures for the user's message, which shrinks the system message.
+    pub disable_procedures: bool,
+    pub stop_conditions: StopConditions,
+    pub timeouts: Timeouts,
+    pub language_map: LanguageMap,
+    /// Sandbox the interpreters of `sandboxed_languages` start in.
+    pub sandbox: Option<Sandbox>,
+    pub sandboxed_languages: HashSet<String>,
+    /// What network the interpreters may reach, told to the model in the system message.
+    pub network: NetworkMode,
+    /// Hosts reachable through `proxy` in [`NetworkMode::Allowlist`].
+    pub allowed_hosts: Vec<String>,
+    proxy: Option<Proxy>,
+    pricing: Option<Pricing>,
+    /// Every message except the system message, which is rebuilt for each turn.
+    messages: Vec<ChatCompletionRequestMessage>,
+    /// Interpreters started during this session, by kernel name. A language's own kernel is
+    /// named after it.
+    code_interpreters: HashMap<String, Box<dyn SubprocessCodeInterpreter>>,
+    artifacts: Artifacts,
+    usage: Usage,
+}
+
+impl Session {
+    pub fn new(client: Client<OpenAIConfig>, model: impl Into<String>) -> Self {
+        let model = model.into();
+        Session {
+            client,
+            pricing: Pricing::for_model(&model),
+            model,
+            system_message: DEFAULT_SYSTEM_MESSAGE.to_string(),
+            temperature: 0.0,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            context_window: None,
+            max_output: None,
+            report_resources: false,
+            auto_run: false,
+            allowed_languages: HashSet::new(),
+            safe_mode: SafeMode::Off,
+            policy: Policy::default(),
+            disable_procedures: false,
+            stop_conditions: StopConditions::default(),
+            timeouts: Timeouts::default(),
+            language_map: LanguageMap::default(),
+            sandbox: None,
+            sandboxed_languages: HashSet::new(),
+            network: NetworkMode::Full,
+            allowed_hosts: Vec::new(),
+            proxy: None,
+            messages: Vec::new(),
+            code_interpreters: HashMap::new(),
+            artifacts: Artifacts::default(),
+            usage: Usage::default(),
+        }
+    }
+
+    /// Session configured by `OPENAI_API_KEY`, `OPENAI_BASE_URL`, `OPENAI_MODEL` and the
+    /// `CODE_INTERPRETER_*` variables.
+    pub fn from_env() -> Result<Self> {
+        Session::from_config(&Config::from_env())
+    }
+
+    /// Session configured by `config`, the `CODE_INTERPRETER_*` variables fill in the rest.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let local = config.local();
+        let api_key = match &config.api_key {
+            Some(api_key) => api_key.clone(),
+            // LM Studio does not check the key.
+            None if local => "lm-studio".to_string(),
+            None => bail!("OPENAI_API_KEY or API_KEY environment variable must be set, or pass --api_key"),
+        };
+        let mut openai_config = OpenAIConfig::new().with_api_key(api_key);
+        match &config.api_base {
+            Some(api_base) => openai_config = openai_config.with_api_base(api_base),
+            None if local => openai_config = openai_config.with_api_base(LOCAL_API_BASE),
+            None => {}
+        }
+
+        let model = config.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        let mut session = Session::new(Client::with_config(openai_config), model);
+        if let Some(system_message) = &config.system_message {
+            session.system_message = system_message.clone();
+        }
+        if let Some(temperature) = config.temperature {
+            session.temperature = temperature;
+        }
+        if let Some(max_tokens) = config.max_tokens {
+            session.max_tokens = max_tokens;
+        }
+        session.context_window = config.context_window;
+        let max_output_tokens = config.max_output_tokens.map(|tokens| tokens as usize * CHARS_PER_TOKEN);
+        session.max_output = match (config.max_output, max_output_tokens) {
+            (Some(chars), Some(tokens)) => Some(chars.min(tokens)),
+            (chars, tokens) => chars.or(tokens),
+        };
+        session.report_resources = config.report_resources.unwrap_or(false);
+        session.auto_run = config.auto_run();
+        session.safe_mode = config.safe_mode.unwrap_or_default();
+        session.policy = Policy::load(config.policy_file.as_deref())?;
+        // Procedures are looked up for OpenAI models only.
+        session.disable_procedures = config.disable_procedures.unwrap_or(local);
+
+        session.stop_conditions = StopConditions::from_env();
+        if let Some(max_budget) = config.max_budget {
+            session.stop_conditions.max_budget = Some(max_budget);
+        }
+        if session.stop_conditions.max_budget.is_some() && session.pricing.is_none() {
+            bail!(
+                "a budget is set but the price of `{}` is unknown, set CODE_INTERPRETER_PROMPT_PRICE and \
+                 CODE_INTERPRETER_COMPLETION_PRICE in USD per million tokens",
+                session.model
+            );
+        }
+        session.timeouts = Timeouts::from_config(config)?;
+        if config.vision.unwrap_or(false) {
+            session.language_map.register("python", || Box::new(Jupyter::python()));
+        }
+        if config.pty.unwrap_or(false) {
+            session.language_map.register("shell", || Box::new(Shell::with_pty()));
+        }
+        session.network = config.network.unwrap_or_default();
+        let kernels = if session.network == NetworkMode::None {
+            // Fail now rather than on the first block.
+            Sandbox::network_only(Network::None)?;
+            if config.vision.unwrap_or(false) {
+                bail!("--vision runs python in a Jupyter kernel, which needs the network to reach it, use another --network");
+            }
+            Vec::new()
+        } else {
+            // Kernels are reached over loopback, which they lose without a network.
+            let kernels = session.language_map.register_kernels(&KernelSpec::all());
+            if !kernels.is_empty() {
+                info!("Running {} in Jupyter kernels", kernels.join(", "));
+            }
+            kernels
+        };
+        session.allowed_hosts = config.allowed_hosts.clone().unwrap_or_default();
+        if session.network == NetworkMode::Allowlist {
+            session.proxy = Some(Proxy::start(AllowList::new(&session.allowed_hosts))?);
+        }
+        if let Some(sandbox) = &config.sandbox {
+            session.sandbox = Some(Sandbox::from_config(sandbox, std::env::current_dir()?)?);
+            session.sandboxed_languages = sandbox.languages.iter().cloned().collect();
+            // Kernels can do anything python and shell can, leaving them out would get around the sandbox.
+            if ["python", "shell"].iter().any(|language| session.sandboxed_languages.contains(*language)) {
+                session.sandboxed_languages.extend(kernels.iter().map(|language| language.to_string()));
+            }
+        }
+        Ok(session)
+    }
+
+    /// Messages exchanged so far, without the system message.
+    pub fn messages(&self) -> &[ChatCompletionRequestMessage] {
+        &self.messages
+    }
+
+    /// Tokens spent on the model during this session.
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// Cost of this session in USD, `None` if the price of the model is unknown.
+    pub fn cost(&self) -> Option<f64> {
+        self.pricing.map(|pricing| self.usage.cost(pricing))
+    }
+
+    /// Forgets the conversation and stops all interpreters.
+    pub fn reset(&mut self) {
+        self.messages.clear();
+        self.terminate();
+    }
+
+    /// Stops all interpreters, they are started again when the model runs code.
+    pub fn terminate(&mut self) {
+        for code_interpreter in self.code_interpreters.values_mut() {
+            code_interpreter.terminate();
+        }
+        self.code_interpreters.clear();
+    }
+
+    /// The kernels started during this session, ordered by name.
+    pub fn kernels(&mut self) -> Vec<KernelStatus> {
+        let mut kernels: Vec<KernelStatus> = self
+            .code_interpreters
+            .iter_mut()
+            .map(|(name, code_interpreter)| KernelStatus {
+                name: name.clone(),
+                proper_name: code_interpreter.proper_name(),
+                pid: code_interpreter.is_running().then(|| code_interpreter.pid()).flatten(),
+            })
+            .collect();
+        kernels.sort_by(|a, b| a.name.cmp(&b.name));
+        kernels
+    }
+
+    /// Starts the kernel `name` again, losing its state.
+    pub fn restart_kernel(&mut self, name: &str) -> Result<()> {
+        let code_interpreter = self.code_interpreters.get_mut(name).ok_or_else(|| anyhow!("no kernel named `{name}`"))?;
+        code_interpreter.terminate();
+        code_interpreter.start_process()
+    }
+
+    /// Stops the kernel `name`, the next code sent to it starts a new one.
+    pub fn shutdown_kernel(&mut self, name: &str) -> Result<()> {
+        let mut code_interpreter = self.code_interpreters.remove(name).ok_or_else(|| anyhow!("no kernel named `{name}`"))?;
+        code_interpreter.terminate();
+        Ok(())
+    }
+
+    /// Appends `message` to the conversation and answers it, running code until the model is
+    /// done or a stop condition is hit.
+    pub async fn chat(&mut self, message: &str) -> Result<(String, StopReason)> {
+        let user_message = ChatCompletionRequestUserMessageArgs::default().content(message).build()?;
+
+        // Add dynamic components, like the user's OS, username, relevant procedures, etc
+        let user_info = get_user_info_string();
+        let mut instructions = match self.network_instructions() {
+            // The default system message promises the internet.
+            Some(network) => format!("{}\n{network}\n\n{user_info}", self.system_message.replace("You can access the internet. ", "")),
+            None => format!("{}\n\n{user_info}", self.system_message),
+        };
+        if !self.disable_procedures {
+            let procedures = get_relevant_procedures_string(&user_message).await?;
+            instructions = instructions + "\n\n" + &procedures;
+        }
+        // Add OpenAI's recommended function message
+        instructions += "\n\nOnly use the function you have been provided with.";
+        let system_message: ChatCompletionRequestMessage =
+            ChatCompletionRequestSystemMessageArgs::default().content(instructions).build()?.into();
+
+        debug!("User message: {message}");
+        self.messages.push(user_message.into());
+
+        let mut consecutive_failures = 0;
+        let mut step = 0;
+        let mut final_contents = String::new();
+        let stop_reason = loop {
+            if step >= self.stop_conditions.max_steps {
+                break StopReason::StepLimit(self.stop_conditions.max_steps);
+            }
+            if let (Some(limit), Some(pricing)) = (self.stop_conditions.max_budget, self.pricing) {
+                let spent = self.usage.cost(pricing);
+                if spent >= limit {
+                    break StopReason::Budget { spent, limit };
+                }
+            }
+            step += 1;
+            debug!("Step {step} of at most {}", self.stop_conditions.max_steps);
+            let history = match self.context_window {
+                Some(context_window) => {
+                    let budget = context_window.saturating_sub(self.max_tokens) as usize * CHARS_PER_TOKEN;
+                    fit_context_window(&self.messages, budget.saturating_sub(message_chars(&system_message)))
+                }
+                None => &self.messages[..],
+            };
+            let messages: Vec<ChatCompletionRequestMessage> =
+                iter::once(system_message.clone()).chain(history.iter().cloned()).collect();
+
+            let request = CreateChatCompletionRequestArgs::default()
+                .max_tokens(self.max_tokens)
+                .model(&self.model)
+                .messages(messages.clone())
+                .tools([ChatCompletionToolArgs::default()
+                    .function(
+                        FunctionObjectArgs::default()
+                            .name("execute")
+                            .description("Executes code on the user's machine, **in the users local environment**, and returns the output")
+                            .parameters(json!({
+                                "type": "object",
+                                "properties": {
+                                    "language": {
+                                        "type": "string",
+                                        "description": "The programming language (required parameter to the `execute` function)",
+                                        "enum": self.language_map.languages().collect::<Vec<_>>(),
+                                    },
+                                    "code": {"type": "string", "description": "The code to execute (required)"},
+                                    "kernel": {
+                                        "type": "string",
+                                        "description": "Name of the kernel to run the code in, which keeps its state between calls. Defaults to the language, pick another name like `scratch` for a separate one",
+                                    },
+                                },
+                                "required": ["language", "code"],
+                            }))
+                            .build()?,
+                    )
+                    .build()?])
+                .tool_choice(ChatCompletionToolChoiceOption::Auto)
+                .temperature(self.temperature)
+                .stream_options(ChatCompletionStreamOptions { include_usage: true })
+                .build()?;
+
+            let mut stream = match self.client.chat().create_stream(request).await {
+                Ok(stream) => stream,
+                Err(err) => break StopReason::Error(err.to_string()),
+            };
+
+            let mut tool_calls = ToolCallAccumulator::new();
+            let mut contents = String::new();
+            let mut finish_reason = None;
+            let mut step_usage = None;
+            let mut stream_error = None;
+            let mut interrupted = false;
+
+            loop {
+                // Ctrl-C while the model is answering ends this turn.
+                let result = tokio::select! {
+                    result = stream.next() => result,
+                    _ = tokio::signal::ctrl_c() => {
+                        println!("\nCTRL-C");
+                        interrupted = true;
+                        None
+                    }
+                };
+                let Some(result) = result else {
+                    break;
+                };
+                match result {
+                    Ok(response) => {
+                        if let Some(response_usage) = response.usage {
+                            step_usage = Some(response_usage);
+                        }
+                        for chat_choice in response.choices {
+                            for chunk in chat_choice.delta.tool_calls.into_iter().flatten() {
+                                tool_calls.push(chunk);
+                            }
+                            if let Some(content) = chat_choice.delta.content {
+                                contents.push_str(&content);
+                            }
+                            if chat_choice.finish_reason.is_some() {
+                                finish_reason = chat_choice.finish_reason;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        stream_error = Some(err.to_string());
+                        break;
+                    }
+                }
+            }
+
+            match step_usage {
+                Some(step_usage) => self.usage.add(step_usage.prompt_tokens.into(), step_usage.completion_tokens.into()),
+                None => {
+                    let prompt_chars = messages.iter().map(message_chars).sum();
+                    self.usage.add_estimate(prompt_chars, contents.len() + tool_calls.streamed_chars());
+                }
+            }
+
+            if interrupted {
+                break StopReason::Interrupted;
+            }
+            if let Some(err) = stream_error {
+                break StopReason::Error(err);
+            }
+
+            if !tool_calls.is_empty() {
+                // Some servers end tool calls with `stop`, the calls themselves tell them apart.
+                if !contents.is_empty() {
+                    println!("{contents}\n");
+                }
+                let tool_calls = tool_calls.finish();
+                let mut assistant_msg = ChatCompletionRequestAssistantMessageArgs::default();
+                if !contents.is_empty() {
+                    assistant_msg.content(contents);
+                }
+                self.messages.push(assistant_msg.tool_calls(tool_calls.clone()).build()?.into());
+
+                // Every call needs a result, errors go back to the model so it can fix its call.
+                // Calls run one after another in the order given, later ones may use the state of
+                // earlier ones and each may need the user's approval.
+                for tool_call in tool_calls {
+                    let output_msg = match self.run_tool_call(&tool_call.function).await {
+                        Ok(result) => {
+                            // Blocks that didn't run say nothing about whether the model is stuck.
+                            match result.status {
+                                Termination::Declined | Termination::Refused | Termination::Denied => {}
+                                _ if result.success() => consecutive_failures = 0,
+                                _ => consecutive_failures += 1,
+                            }
+                            result.to_message_content()
+                        }
+                        Err(err) => {
+                            consecutive_failures += 1;
+                            format!("Error: {err}")
+                        }
+                    };
+                    debug!("{} output: {}", tool_call.function.name, output_msg);
+                    let tool_msg = ChatCompletionRequestToolMessageArgs::default()
+                        .content(output_msg)
+                        .tool_call_id(tool_call.id)
+                        .build()?;
+                    self.messages.push(tool_msg.into());
+                }
+
+                if consecutive_failures >= self.stop_conditions.max_consecutive_failures {
+                    break StopReason::ConsecutiveFailures(consecutive_failures);
+                }
+                continue;
+            }
+
+            match finish_reason {
+                Some(FinishReason::Length) => {
+                    // The answer was cut off, keep it and let the model carry on.
+                    final_contents.push_str(&contents);
+                    let assistant_msg = ChatCompletionRequestAssistantMessageArgs::default().content(contents).build()?;
+                    self.messages.push(assistant_msg.into());
+                }
+                Some(FinishReason::ContentFilter) => {
+                    final_contents.push_str(&contents);
+                    break StopReason::Error("the answer was blocked by the content filter".to_string());
+                }
+                _ => {
+                    // No tool call, the model is done.
+                    final_contents.push_str(&contents);
+                    break StopReason::Finished;
+                }
+            }
+        };
+
+
+        match self.cost() {
+            Some(cost) => info!("Used {} tokens (${cost:.4}) so far, {step} steps this turn", self.usage.total_tokens()),
+            None => info!("Used {} tokens so far, {step} steps this turn", self.usage.total_tokens()),
+        }
+        Ok((final_contents, stop_reason))
+    }
+
+    /// Runs the function the model called, only `execute` is offered.
+    async fn run_tool_call(&mut self, function: &FunctionCall) -> Result<ExecutionResult> {
+        if function.name != "execute" {
+            bail!("unknown function `{}`, only `execute` is available", function.name);
+        }
+        let arguments: Value = serde_json::from_str(&function.arguments)
+            .map_err(|err| anyhow!("the arguments of `execute` are not valid JSON: {err}"))?;
+        let language = arguments.get("language").and_then(Value::as_str);
+        let code = arguments.get("code").and_then(Value::as_str);
+        let (Some(language), Some(code)) = (language, code) else {
+            bail!("`execute` needs both a `language` and a `code` argument");
+        };
+        // Languages are registered in lowercase, models sometimes write `R` or `Python`.
+        let language = &language.to_lowercase();
+        let kernel = arguments.get("kernel").and_then(Value::as_str).filter(|kernel| !kernel.is_empty()).unwrap_or(language);
+
+        if let Some(denied) = self.enforce_policy(language, kernel, code).await {
+            return Ok(denied);
+        }
+        let extension = self.code_interpreter(language, kernel)?.file_extension();
+        let approved_code = match self.approve(language, kernel, code, extension).await? {
+            Decision::Run(approved_code) => approved_code,
+            Decision::Declined => {
+                println!("{}", "Skipped.".yellow());
+                return Ok(ExecutionResult::declined(language));
+            }
+            Decision::Refused(scan) => {
+                println!("{}", "Refused by safe mode.".red());
+                let findings: Vec<String> = scan.findings.iter().map(ToString::to_string).collect();
+                let reason = format!("safe mode refuses to run risky code: {}", findings.join("; "));
+                return Ok(ExecutionResult::refused(language, reason));
+            }
+        };
+        // The user may have edited in something the policy denies.
+        if approved_code != code {
+            if let Some(denied) = self.enforce_policy(language, kernel, &approved_code).await {
+                return Ok(denied);
+            }
+        }
+        let mut result = self.execute(language, kernel, &approved_code).await?;
+        if approved_code != code {
+            result.edited_code = Some(approved_code);
+        }
+        Ok(result)
+    }
+
+    /// Result to send back if the policy does not let `code` run, `None` if it may.
+    async fn enforce_policy(&mut self, language: &str, kernel: &str, code: &str) -> Option<ExecutionResult> {
+        if self.policy.is_empty() {
+            return None;
+        }
+        let cwd = self.cwd(kernel);
+        match self.policy.check(language, code, &cwd).await {
+            Ok(denials) if denials.is_empty() => None,
+            Ok(denials) => {
+                println!("{}", "Denied by policy:".red());
+                for denial in &denials {
+                    println!("{}", format!("  {denial}").red());
+                }
+                Some(ExecutionResult::denied(language, denials))
+            }
+            Err(err) => {
+                warn!("Failed to check the policy: {err}");
+                println!("{}", "Refused, the policy could not be checked.".red());
+                Some(ExecutionResult::refused(language, format!("the policy could not be checked: {err}")))
+            }
+        }
+    }
+
+    /// Shows `code` with what safe mode found in it and asks the user whether to run it.
+    ///
+    /// The user is not asked if `auto_run` is set or they always allow `language`, unless safe
+    /// mode is `ask` and the code is risky. In `auto` mode risky code is refused outright.
+    async fn approve(&mut self, language: &str, kernel: &str, code: &str, extension: &str) -> Result<Decision> {
+        let mut code = code.to_string();
+        loop {
+            print_highlighted_code(&code, extension);
+            let scan = self.scan(language, kernel, &code).await;
+            for finding in &scan.findings {
+                let line = format!("  {finding}");
+                match finding.risk {
+                    RiskLevel::High => println!("{}", line.red()),
+                    RiskLevel::Medium => println!("{}", line.yellow()),
+                    RiskLevel::Low => println!("{}", line.dimmed()),
+                }
+            }
+            if scan.is_risky() && self.safe_mode == SafeMode::Auto {
+                return Ok(Decision::Refused(scan));
+            }
+
+            let confirm_risk = scan.is_risky() && self.safe_mode == SafeMode::Ask;
+            if !confirm_risk && (self.auto_run || self.allowed_languages.contains(language)) {
+                return Ok(Decision::Run(code));
+            }
+            match ask_approval(language).await? {
+                Approval::Run => return Ok(Decision::Run(code)),
+                Approval::Skip => return Ok(Decision::Declined),
+                Approval::Edit => code = edit_code(&code, extension).await?,
+                Approval::AlwaysAllow => {
+                    self.allowed_languages.insert(language.to_string());
+                    return Ok(Decision::Run(code));
+                }
+            }
+        }
+    }
+
+    /// What safe mode finds in `code`, run in the current directory of its interpreter.
+    async fn scan(&mut self, language: &str, kernel: &str, code: &str) -> Scan {
+        if self.safe_mode == SafeMode::Off {
+            return Scan::default();
+        }
+        let cwd = self.cwd(kernel);
+        safety::scan(language, code, &cwd).await
+    }
+
+    /// Current directory of the interpreter of `kernel`, or ours if it hasn't started yet.
+    fn cwd(&mut self, kernel: &str) -> PathBuf {
+        self.code_interpreters
+            .get_mut(kernel)
+            .and_then(|code_interpreter| code_interpreter.cwd())
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default()
+    }
+
+    /// What the model is told about the network, `None` if it is not restricted.
+    fn network_instructions(&self) -> Option<String> {
+        match self.network {
+            NetworkMode::Full => None,
+            NetworkMode::None => Some(
+                "The code you run has **no network access**: you can't reach the internet, download files or install packages, so only use what is already installed."
+                    .to_string(),
+            ),
+            NetworkMode::Allowlist => Some(format!(
+                "The code you run may only reach these hosts, through the HTTP(S) proxy set in `HTTP_PROXY` and `HTTPS_PROXY`: {}. The proxy refuses other hosts, don't connect around it.",
+                self.allowed_hosts.join(", ")
+            )),
+        }
+    }
+
+    /// Network of the interpreters, cut off entirely if the proxy of an allow-list is missing.
+    fn sandbox_network(&self) -> Network {
+        match (self.network, &self.proxy) {
+            (NetworkMode::Full, _) => Network::Full,
+            (NetworkMode::Allowlist, Some(proxy)) => Network::Proxy(proxy.url()),
+            (NetworkMode::None | NetworkMode::Allowlist, _) => Network::None,
+        }
+    }
+
+    /// Interpreter of this session named `kernel` for `language`, created on first use.
+    fn code_interpreter(&mut self, language: &str, kernel: &str) -> Result<&mut Box<dyn SubprocessCodeInterpreter>> {
+        let network = self.sandbox_network();
+        match self.code_interpreters.entry(kernel.to_string()) {
+            Entry::Occupied(entry) if entry.get().language() != language => {
+                bail!("the kernel `{kernel}` runs {}, pick another name for a {language} kernel", entry.get().language())
+            }
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => match self.language_map.create(language) {
+                Some(mut code_interpreter) => {
+                    let sandbox = match &self.sandbox {
+                        Some(sandbox) if self.sandboxed_languages.contains(language) => {
+                            let mut sandbox = sandbox.clone();
+                            sandbox.network = network;
+                            Some(sandbox)
+                        }
+                        _ if network != Network::Full => Some(Sandbox::network_only(network)?),
+                        _ => None,
+                    };
+                    if let Some(sandbox) = sandbox {
+                        if !code_interpreter.set_sandbox(sandbox) {
+                            bail!("{} can't run sandboxed, which the settings require for `{language}`", code_interpreter.proper_name());
+                        }
+                    }
+                    Ok(entry.insert(code_interpreter))
+                }
+                None => {
+                    warn!("No match found {:?} language", language);
+                    bail!("unsupported language `{language}`, use one of the languages in the `execute` schema");
+                }
+            },
+        }
+    }
+
+    /// Runs `code` in the interpreter of this session named `kernel`, starting it on first use.
+    async fn execute(&mut self, language: &str, kernel: &str, code: &str) -> Result<ExecutionResult> {
+        let timeout = self.timeouts.for_language(language);
+        // Output that will be cut for the model is saved in full, so it can still look at all of it.
+        let mut spill = match self.artifacts.next_path(&format!("{language}-output.txt")) {
+            Ok(path) => Some(Spill::new(path, self.max_output.unwrap_or(usize::MAX).min(MAX_COLLECTED_OUTPUT))),
+            Err(err) => {
+                warn!("Failed to create the artifacts directory: {err}");
+                None
+            }
+        };
+        let code_interpreter = self.code_interpreter(language, kernel)?;
+        debug!("Found {} code!", code_interpreter.proper_name());
+
+        // Show the output as it is printed, the model gets all of it once the block is done.
+        let mut result = code_interpreters::execute(code_interpreter.as_mut(), code, timeout, |chunk| {
+            match chunk.stream {
+                OutputStream::Stdout => print!("{}", chunk.content),
+                OutputStream::Stderr => print!("{}", chunk.content.red()),
+            }
+            let _ = stdout().flush();
+            if let Some(spill) = &mut spill {
+                spill.write(chunk);
+            }
+        }, |prompt| async move {
+            match ask_input(&prompt).await {
+                Ok(answer) => answer,
+                Err(err) => {
+                    warn!("Failed to ask for input: {err}");
+                    None
+                }
+            }
+        })
+        .await?;
+        let full_output = spill.and_then(Spill::finish);
+        if result.elide(self.max_output, full_output.as_deref()) {
+            if let Some(path) = &full_output {
+                println!("{}", format!("The model gets part of the output, all of it is in {}.", path.display()).dimmed());
+            }
+        } else if let Some(path) = full_output {
+            let _ = std::fs::remove_file(path);
+        }
+
+        match result.status {
+            Termination::Finished if !result.success() => {
+                println!("{}", format!("Exit code {}.", result.exit_code.unwrap_or_default()).red())
+            }
+            Termination::Finished => {}
+            Termination::TimedOut => println!("{}", format!("Timed out after {}s.", result.duration.as_secs()).red()),
+            Termination::Interrupted => println!("{}", "Interrupted.".red()),
+            Termination::AwaitingInput => println!("{}", "Stopped waiting for input.".red()),
+            Termination::Declined | Termination::Refused | Termination::Denied => {}
+        }
+        if let Some(resources) = &result.resources {
+            println!("{}", resources.summary(result.duration).dimmed());
+            info!(
+                language,
+                kernel,
+                wall_secs = result.duration.as_secs_f64(),
+                cpu_user_secs = resources.cpu_user_secs,
+                cpu_system_secs = resources.cpu_system_secs,
+                peak_rss_bytes = resources.peak_rss_bytes,
+                stdout_bytes = resources.stdout_bytes,
+                stderr_bytes = resources.stderr_bytes,
+                "Executed a block"
+            );
+        }
+        if !self.report_resources {
+            result.resources = None;
+        }
+        Ok(result)
+    }
+}
+
+/// A kernel of a [`Session`], as listed by [`Session::kernels`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelStatus {
+    pub name: String,
+    pub proper_name: &'static str,
+    /// Process of the kernel, `None` once it exited.
+    pub pid: Option<u32>,
+}
+
+/// What to do with a block of code the model wants to run.
+enum Decision {
+    Run(String),
+    Declined,
+    Refused(Scan),
+}
+
+fn message_chars(message: &ChatCompletionRequestMessage) -> usize {
+    serde_json::to_string(message).map_or(0, |message| message.len())
+}
+
+/// Most recent messages that fit in `budget` characters, keeping at least the last one.
+///
+/// Tool results cannot come without the call they answer, so an assistant message calling
+/// tools is left out together with its results, and kept with them if it is the last one.
+fn fit_context_window(messages: &[ChatCompletionRequestMessage], budget: usize) -> &[ChatCompletionRequestMessage] {
+    let mut total: usize = messages.iter().map(message_chars).sum();
+    let mut start = 0;
+    loop {
+        let mut next = start + 1;
+        while next < messages.len() && matches!(messages[next], ChatCompletionRequestMessage::Tool(_)) {
+            next += 1;
+        }
+        if total <= budget || next >= messages.len() {
+            break;
+        }
+        total -= messages[start..next].iter().map(message_chars).sum::<usize>();
+        start = next;
+    }
+    if start > 0 {
+        debug!("Left {start} old messages out to fit the context window");
+    }
+    &messages[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_session_keeps_interpreters_until_reset() {
+        let mut session = Session::new(Client::new(), "gpt-4o");
+        session.execute("shell", "shell", "export SESSION_VAR=kept").await.unwrap();
+        let result = session.execute("shell", "shell", "echo $SESSION_VAR").await.unwrap();
+        assert_eq!(result.stream_text(OutputStream::Stdout), "kept\n");
+
+        session.reset();
+        let result = session.execute("shell", "shell", "echo ${SESSION_VAR:-gone}").await.unwrap();
+        assert_eq!(result.stream_text(OutputStream::Stdout), "gone\n");
+        assert!(session.execute("cobol", "cobol", "DISPLAY 'HI'").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_named_kernels_keep_separate_state() {
+        let mut session = Session::new(Client::new(), "gpt-4o");
+        session.execute("shell", "shell", "export SESSION_VAR=main").await.unwrap();
+        session.execute("shell", "scratch", "export SESSION_VAR=scratch").await.unwrap();
+        let result = session.execute("shell", "shell", "echo $SESSION_VAR").await.unwrap();
+        assert_eq!(result.stream_text(OutputStream::Stdout), "main\n");
+        assert!(session.execute("python", "scratch", "print(1)").await.is_err());
+
+        let names: Vec<_> = session.kernels().into_iter().map(|kernel| kernel.name).collect();
+        assert_eq!(names, ["scratch", "shell"]);
+        session.restart_kernel("shell").unwrap();
+        let result = session.execute("shell", "shell", "echo ${SESSION_VAR:-gone}").await.unwrap();
+        assert_eq!(result.stream_text(OutputStream::Stdout), "gone\n");
+        session.shutdown_kernel("scratch").unwrap();
+        assert!(session.shutdown_kernel("scratch").is_err());
+        assert_eq!(session.kernels().len(), 1);
+    }
+
+    #[test]
+    fn test_budget_needs_pricing() {
+        let config = Config { api_key: Some("key".to_string()), model: Some("my-local-model".to_string()), max_budget: Some(1.0), ..Config::default() };
+        let err = Session::from_config(&config).err().unwrap();
+        assert!(err.to_string().contains("price of `my-local-model` is unknown"), "{err}");
+        let config = Config { model: Some("gpt-4o".to_string()), ..config };
+        assert_eq!(Session::from_config(&config).unwrap().pricing, Pricing::for_model("gpt-4o"));
+    }
+
+    #[test]
+    fn test_fit_context_window_drops_orphaned_tool_results() {
+        let user = |content: &str| -> ChatCompletionRequestMessage {
+            ChatCompletionRequestUserMessageArgs::default().content(content).build().unwrap().into()
+        };
+        let tool = |content: &str| -> ChatCompletionRequestMessage {
+            ChatCompletionRequestToolMessageArgs::default().content(content).tool_call_id("call_0").build().unwrap().into()
+        };
+        let assistant: ChatCompletionRequestMessage =
+            ChatCompletionRequestAssistantMessageArgs::default().content("x".repeat(100)).build().unwrap().into();
+        let messages = [user("first"), assistant, tool("result"), user("second")];
+
+        assert_eq!(fit_context_window(&messages, usize::MAX).len(), 4);
+        assert_eq!(fit_context_window(&messages, message_chars(&messages[3]) + 50), &messages[3..]);
+        assert_eq!(fit_context_window(&messages, 0), &messages[3..]);
+
+        // Trimming in the middle of a round-trip keeps the call along with its results.
+        let messages = &messages[..3];
+        assert_eq!(fit_context_window(messages, message_chars(&messages[2]) + 50), &messages[1..]);
+        assert_eq!(fit_context_window(messages, 0), &messages[1..]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::env;
+use std::fmt;
+
+/// Limits that end the agent loop before the model says it is done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopConditions {
+    /// Most requests to the model for one user message.
+    pub max_steps: usize,
+    /// Most executions in a row that may fail before giving up.
+    pub max_consecutive_failures: usize,
+    /// Most USD to spend on the model, `None` for no limit.
+    pub max_budget: Option<f64>,
+}
+
+impl StopConditions {
+    /// Reads `CODE_INTERPRETER_MAX_STEPS`, `CODE_INTERPRETER_MAX_FAILURES` and
+    /// `CODE_INTERPRETER_MAX_BUDGET`, falling back to the defaults.
+    pub fn from_env() -> Self {
+        let mut stop_conditions = StopConditions::default();
+        if let Some(max_steps) = env::var("CODE_INTERPRETER_MAX_STEPS").ok().and_then(|value| value.parse().ok()) {
+            stop_conditions.max_steps = max_steps;
+        }
+        if let Some(max_failures) = env::var("CODE_INTERPRETER_MAX_FAILURES").ok().and_then(|value| value.parse().ok()) {
+            stop_conditions.max_consecutive_failures = max_failures;
+        }
+        if let Some(max_budget) = env::var("CODE_INTERPRETER_MAX_BUDGET").ok().and_then(|value| value.parse().ok()) {
+            stop_conditions.max_budget = Some(max_budget);
+        }
+        stop_conditions
+    }
+}
+
+impl Default for StopConditions {
+    fn default() -> Self {
+        StopConditions { max_steps: 30, max_consecutive_failures: 5, max_budget: None }
+    }
+}
+
+/// Why the agent loop stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// The model answered without calling a function.
+    Finished,
+    StepLimit(usize),
+    Budget { spent: f64, limit: f64 },
+    ConsecutiveFailures(usize),
+    Interrupted,
+    Error(String),
+}
+
+impl StopReason {
+    pub fn is_finished(&self) -> bool {
+        *self == StopReason::Finished
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Finished => write!(f, "finished"),
+            StopReason::StepLimit(max_steps) => write!(f, "stopped after reaching the limit of {max_steps} steps"),
+            StopReason::Budget { spent, limit } => {
+                write!(f, "stopped after spending ${spent:.4} of the ${limit:.4} budget")
+            }
+            StopReason::ConsecutiveFailures(failures) => {
+                write!(f, "stopped after {failures} failed executions in a row")
+            }
+            StopReason::Interrupted => write!(f, "interrupted by the user"),
+            StopReason::Error(err) => write!(f, "stopped by an error: {err}"),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionMessageToolCallChunk, ChatCompletionToolType, FunctionCall,
+};
+
+/// Assembles the tool calls of one streamed assistant message.
+///
+/// Each delta carries the index of the call it belongs to, so parallel calls can be interleaved.
+/// The id, type and name arrive whole, the arguments are split across many deltas.
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    tool_calls: BTreeMap<u32, ChatCompletionMessageToolCall>,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        ToolCallAccumulator::default()
+    }
+
+    pub fn push(&mut self, chunk: ChatCompletionMessageToolCallChunk) {
+        let tool_call = self.tool_calls.entry(chunk.index).or_insert_with(|| ChatCompletionMessageToolCall {
+            id: String::new(),
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionCall { name: String::new(), arguments: String::new() },
+        });
+        if let Some(id) = chunk.id {
+            tool_call.id = id;
+        }
+        if let Some(r#type) = chunk.r#type {
+            tool_call.r#type = r#type;
+        }
+        if let Some(function) = chunk.function {
+            // Some servers repeat the name in later deltas.
+            if let Some(name) = function.name.filter(|_| tool_call.function.name.is_empty()) {
+                tool_call.function.name = name;
+            }
+            if let Some(arguments) = function.arguments {
+                tool_call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tool_calls.is_empty()
+    }
+
+    /// Number of characters streamed for the calls, to estimate their tokens.
+    pub fn streamed_chars(&self) -> usize {
+        self.tool_calls.values().map(|tool_call| tool_call.function.name.len() + tool_call.function.arguments.len()).sum()
+    }
+
+    /// Tool calls in index order. Servers that leave out ids get one made up from the index, since
+    /// each result has to name the call it answers.
+    pub fn finish(self) -> Vec<ChatCompletionMessageToolCall> {
+        self.tool_calls
+            .into_iter()
+            .map(|(index, mut tool_call)| {
+                if tool_call.id.is_empty() {
+                    tool_call.id = format!("call_{index}");
+                }
+                tool_call
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::FunctionCallStream;
+
+    use super::*;
+
+    fn chunk(index: u32, id: Option<&str>, name: Option<&str>, arguments: &str) -> ChatCompletionMessageToolCallChunk {
+        ChatCompletionMessageToolCallChunk {
+            index,
+            id: id.map(str::to_string),
+            r#type: id.map(|_| ChatCompletionToolType::Function),
+            function: Some(FunctionCallStream { name: name.map(str::to_string), arguments: Some(arguments.to_string()) }),
+        }
+    }
+
+    #[test]
+    fn test_assembles_interleaved_calls_by_index() {
+        let mut tool_calls = ToolCallAccumulator::new();
+        tool_calls.push(chunk(0, Some("call_a"), Some("execute"), ""));
+        tool_calls.push(chunk(1, Some("call_b"), Some("execute"), "{\"language\":"));
+        tool_calls.push(chunk(0, None, None, "{\"language\": \"shell\""));
+        tool_calls.push(chunk(1, None, None, " \"python\"}"));
+        tool_calls.push(chunk(0, None, None, "}"));
+
+        let tool_calls = tool_calls.finish();
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].id, "call_a");
+        assert_eq!(tool_calls[0].function.arguments, "{\"language\": \"shell\"}");
+        assert_eq!(tool_calls[1].id, "call_b");
+        assert_eq!(tool_calls[1].function.arguments, "{\"language\": \"python\"}");
+    }
+
+    #[test]
+    fn test_makes_up_missing_ids() {
+        let mut tool_calls = ToolCallAccumulator::new();
+        tool_calls.push(chunk(3, None, Some("execute"), "{}"));
+        tool_calls.push(chunk(3, None, Some("execute"), ""));
+        let tool_calls = tool_calls.finish();
+        assert_eq!(tool_calls[0].id, "call_3");
+        assert_eq!(tool_calls[0].function.name, "execute");
+    }
+}
@@ -0,0 +1,84 @@
+use std::env;
+
+use crate::core::session::CHARS_PER_TOKEN;
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// Well known models, matched by prefix, more specific names first.
+const PRICES: &[(&str, Pricing)] = &[
+    ("gpt-4o-mini", Pricing { prompt: 0.15, completion: 0.6 }),
+    ("gpt-4o", Pricing { prompt: 2.5, completion: 10.0 }),
+    ("gpt-4.1-nano", Pricing { prompt: 0.1, completion: 0.4 }),
+    ("gpt-4.1-mini", Pricing { prompt: 0.4, completion: 1.6 }),
+    ("gpt-4.1", Pricing { prompt: 2.0, completion: 8.0 }),
+    ("gpt-4-turbo", Pricing { prompt: 10.0, completion: 30.0 }),
+    ("gpt-4", Pricing { prompt: 30.0, completion: 60.0 }),
+    ("gpt-3.5-turbo", Pricing { prompt: 0.5, completion: 1.5 }),
+];
+
+impl Pricing {
+    /// Price of `model`, `CODE_INTERPRETER_PROMPT_PRICE` and `CODE_INTERPRETER_COMPLETION_PRICE`
+    /// take precedence over the built-in table.
+    pub fn for_model(model: &str) -> Option<Self> {
+        let prompt = env::var("CODE_INTERPRETER_PROMPT_PRICE").ok().and_then(|price| price.parse().ok());
+        let completion = env::var("CODE_INTERPRETER_COMPLETION_PRICE").ok().and_then(|price| price.parse().ok());
+        if let (Some(prompt), Some(completion)) = (prompt, completion) {
+            return Some(Pricing { prompt, completion });
+        }
+        let model = model.rsplit('/').next().unwrap_or(model);
+        PRICES.iter().find(|(name, _)| model.starts_with(name)).map(|(_, pricing)| *pricing)
+    }
+}
+
+/// Tokens spent on the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, prompt_tokens: u64, completion_tokens: u64) {
+        self.prompt_tokens += prompt_tokens;
+        self.completion_tokens += completion_tokens;
+    }
+
+    /// Adds a request whose usage the server did not report, guessing four characters a token.
+    pub fn add_estimate(&mut self, prompt_chars: usize, completion_chars: usize) {
+        self.add(prompt_chars.div_ceil(CHARS_PER_TOKEN) as u64, completion_chars.div_ceil(CHARS_PER_TOKEN) as u64);
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn cost(&self, pricing: Pricing) -> f64 {
+        (self.prompt_tokens as f64 * pricing.prompt + self.completion_tokens as f64 * pricing.completion) / 1_000_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing_prefers_specific_model() {
+        assert_eq!(Pricing::for_model("gpt-4o-mini-2024-07-18").unwrap().prompt, 0.15);
+        assert_eq!(Pricing::for_model("openai/gpt-4o").unwrap().prompt, 2.5);
+        assert_eq!(Pricing::for_model("Qwen/Qwen3-Coder-480B-A35B-Instruct"), None);
+    }
+
+    #[test]
+    fn test_cost() {
+        let mut usage = Usage::default();
+        usage.add(1_000_000, 500_000);
+        usage.add_estimate(8, 3);
+        assert_eq!(usage, Usage { prompt_tokens: 1_000_002, completion_tokens: 500_001 });
+        assert_eq!(Usage { prompt_tokens: 1_000_000, completion_tokens: 500_000 }.cost(Pricing { prompt: 2.0, completion: 8.0 }), 6.0);
+    }
+}
@@ -10,7 +10,7 @@ use tracing_subscriber::{
     layer::SubscriberExt
 };
 
-pub fn setup() -> Option<WorkerGuard> {
+pub fn setup(debug_mode: bool) -> Option<WorkerGuard> {
     // local time
     let offset = clia_local_offset::current_local_offset().expect("Can not get local offset!");
     let timer =
@@ -34,8 +34,10 @@ pub fn setup() -> Option<WorkerGuard> {
         .with_writer(non_blocking_appender
         ).with_filter(file_filter());
 
+    // `--debug_mode` shows debug logs on the console too.
+    let console_level = if debug_mode { "code_interpreter=debug" } else { "code_interpreter=warn" };
     let console_filter =
-        || tracing_subscriber::EnvFilter::from_default_env().add_directive("code_interpreter=warn".parse().unwrap());
+        || tracing_subscriber::EnvFilter::from_default_env().add_directive(console_level.parse().unwrap());
 
     let console_layer = fmt::layer()
         .with_timer(timer.clone())
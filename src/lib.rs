@@ -1,7 +1,8 @@
-// mod config;
+pub mod config;
 pub mod init_tracing;
 pub mod rag;
+pub mod safety;
 pub mod utils;
-// pub mod cli;
+pub mod cli;
 pub mod code_interpreters;
-// mod core;
\ No newline at end of file
+pub mod core;
\ No newline at end of file
@@ -1,46 +1,22 @@
 use anyhow::Result;
-use clap::arg;
-use clap::{Arg, Subcommand};
-use code_interpreter::rag::get_relevant_procedures_string::get_relevant_procedures_string;
 
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
 
 use termimad::{mad_print_inline, MadSkin};
-use tracing::{debug, info, warn};
 
-use core::time;
-
-use std::io::{stdout, Write};
-use std::process::{Command, Stdio};
-use std::thread::{self};
-
-use async_openai::config::OpenAIConfig;
 use async_openai::error::OpenAIError;
-use async_openai::types::{
-    ChatCompletionFunctionsArgs, ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestFunctionMessage,
-    ChatCompletionRequestFunctionMessageArgs, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
-    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
-    FinishReason, FunctionCall, Role,
-};
-use async_openai::Client;
+use async_openai::types::Role;
 
+use colored::Colorize;
 use derive_builder::Builder;
-use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Color, FontStyle, Style, ThemeSet};
-use syntect::parsing::SyntaxSet;
-use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
 
 // use code_interpreter::init_tracing::init_tracing;
-use code_interpreter::code_interpreters::languages::{
-    shell::run_shell_command,
-    applescript::run_applescript_command
-};
-use code_interpreter::utils::{check_environments, get_user_info_string};
+use code_interpreter::cli::{cli, config_from_matches};
+use code_interpreter::config::Config;
+use code_interpreter::core::{ReplCommand, Session};
+use code_interpreter::utils::check_environments;
 use code_interpreter::init_tracing;
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone, Builder, PartialEq)]
@@ -64,166 +40,32 @@ pub struct ChatCompletionRespondAssistantMessage {
     pub output: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Argument {
-    pub name: String,
-    pub nickname: String,
-    pub help_text: String,
-    #[serde(rename = "type")]
-    pub argument_type: String,
-}
-
-fn cli() -> clap::Command {
-    let arguments = serde_json::json!(
-        [
-            {
-                "name": "system_message",
-                "nickname": "s",
-                "help_text": "prompt / custom instructions for the language model",
-                "type": "str",
-            },
-            {
-                "name": "local",
-                "nickname": "l",
-                "help_text": "experimentally run the language model locally (via LM Studio)",
-                "type": "bool",
-            },
-            {
-                "name": "auto_run",
-                "nickname": "y",
-                "help_text": "automatically run generated code",
-                "type": "bool",
-            },
-            {
-                "name": "debug_mode",
-                "nickname": "d",
-                "help_text": "run in debug mode",
-                "type": "bool",
-            },
-            {
-                "name": "disable_procedures",
-                "nickname": "dp",
-                "help_text": "disables procedures (RAG of some common OI use-cases). disable to shrink system message. auto-disabled for non-OpenAI models",
-                "type": "bool",
-            },
-            {
-                "name": "model",
-                "nickname": "m",
-                "help_text": "language model to use",
-                "type": "str",
-            },
-            {
-                "name": "temperature",
-                "nickname": "t",
-                "help_text": "optional temperature setting for the language model",
-                "type": "float",
-            },
-            {
-                "name": "context_window",
-                "nickname": "c",
-                "help_text": "optional context window size for the language model",
-                "type": "int",
-            },
-            {
-                "name": "max_tokens",
-                "nickname": "x",
-                "help_text": "optional maximum number of tokens for the language model",
-                "type": "int",
-            },
-            {
-                "name": "max_output",
-                "nickname": "xo",
-                "help_text": "optional maximum number of characters for code outputs",
-                "type": "int",
-            },
-            {
-                "name": "max_budget",
-                "nickname": "b",
-                "help_text": "optionally set the max budget (in USD) for your llm calls",
-                "type": "float",
-            },
-            {
-                "name": "api_base",
-                "nickname": "ab",
-                "help_text": "optionally set the API base URL for your llm calls (this will override environment variables)",
-                "type": "str",
-            },
-            {
-                "name": "api_key",
-                "nickname": "ak",
-                "help_text": "optionally set the API key for your llm calls (this will override environment variables)",
-                "type": "str",
-            },
-            {
-                "name": "safe_mode",
-                "nickname": "safe",
-                "help_text": "optionally enable safety mechanisms like code scanning; valid options are off, ask, and auto",
-                "type": "str",
-                "choices": ["off", "ask", "auto"],
-                "default": "off",
-            },
-            {
-                "name": "config_file",
-                "nickname": "cf",
-                "help_text": "optionally set a custom config file to use",
-                "type": "str",
-            },
-            {
-                "name": "vision",
-                "nickname": "v",
-                "help_text": "experimentally use vision for supported languages (HTML)",
-                "type": "bool",
-            },
-        ]
-    );
-
-    let mut clap = clap::Command::new("Open Interpreter")
-        .about("A Open Code Interpreter CLI")
-        .subcommand_required(true)
-        .arg_required_else_help(true)
-        .allow_external_subcommands(true);
-
-    {
-        // Deserialize the JSON array into a Vec<Argument>
-        let arguments: Vec<Argument> = serde_json::from_value(arguments).expect("Failed to deserialize JSON");
-
-        for arg in arguments {
-            let static_str: &'static str = Box::leak(arg.name.into_boxed_str());
-            // let xo: char = "ox".as_bytes();
-            clap = clap.arg(
-                Arg::new(static_str)
-                    // .short(arg.nickname.chars().next())
-                    .long(static_str)
-                    .help(arg.help_text),
-            )
-        }
-    }
-
-    clap
-}
-
-fn push_args() -> Vec<clap::Arg> {
-    vec![clap::arg!(-m --message <MESSAGE>)]
-}
-
 #[tokio::main]
 async fn main() -> Result<()> {
 
-    let _guard = init_tracing::setup();
-    tracing::info!("Code Interpreter! will require approval before running code.");
+    let matches = cli().get_matches();
+    let config = Config::load(config_from_matches(&matches)?)?;
 
-    // let matches = cli().get_matches();
+    let _guard = init_tracing::setup(config.debug_mode());
+    tracing::info!("Code Interpreter! will require approval before running code.");
 
-    if !check_environments() {
+    if config.api_key.is_none() && !config.local() && !check_environments() {
         return Ok(());
     }
 
     let skin = MadSkin::default();
 
-    println!("\n {}\n", skin.inline("** Code Interpreter!** will require approval before running code."));
-    println!("{}\n", skin.inline("  Use `interpreter -y ` to bypass this."));
+    if config.auto_run() {
+        println!("\n {}\n", skin.inline("** Code Interpreter!** will run code without asking for approval."));
+    } else {
+        println!("\n {}\n", skin.inline("** Code Interpreter!** will require approval before running code."));
+        println!("{}\n", skin.inline("  Use `interpreter -y ` to bypass this."));
+    }
+    println!("{}\n", skin.inline("  Type `%reset` to start a new conversation."));
+    println!("{}\n", skin.inline("  Type `%kernels` to list the kernels, `%restart <kernel>` or `%shutdown <kernel>` to manage them."));
     println!("{}\n", skin.inline("  Press `CTRL-C ` to exit."));
 
+    let mut session = Session::from_config(&config)?;
     let mut rl = DefaultEditor::new()?;
 
     #[cfg(feature = "with-file-history")]
@@ -235,9 +77,13 @@ async fn main() -> Result<()> {
         match readline {
             Ok(line) => {
                 let _ = rl.add_history_entry(line.as_str());
+                if let Some(command) = ReplCommand::parse(&line) {
+                    run_command(&mut session, command);
+                    continue;
+                }
                 // println!("Message: {}", line);
                 // "can you summarize the GitHub repository? https://github.com/KillianLucas/open-interpreter/"
-                let res = interpreter(line).await?;
+                let (res, stop_reason) = session.chat(&line).await?;
 
                 mad_print_inline!(
                     &skin,
@@ -246,6 +92,9 @@ async fn main() -> Result<()> {
                     res,              // fills $1. Note that the stars don't mess the markdown
                 );
                 println!("\n");
+                if !stop_reason.is_finished() {
+                    println!("{}\n", format!("Stopped: {stop_reason}.").yellow());
+                }
             }
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
@@ -275,366 +124,34 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn interpreter(message: String) -> Result<String> {
-    // Get API key from environment variable, fallback to OPENAI_API_KEY
-    let api_key = std::env::var("OPENAI_API_KEY")
-        .or_else(|_| std::env::var("API_KEY"))
-        .expect("OPENAI_API_KEY or API_KEY environment variable must be set");
-
-    // Get base URL from environment variable if set
-    let base_url = std::env::var("OPENAI_BASE_URL")
-        .or_else(|_| std::env::var("BASE_URL"))
-        .ok();
-
-    // Create client with custom configuration
-    let config = if let Some(url) = base_url {
-        OpenAIConfig::new()
-            .with_api_key(api_key)
-            .with_api_base(url)
-    } else {
-        OpenAIConfig::new().with_api_key(api_key)
-    };
-
-    let client = Client::with_config(config);
-
-    let mut instructions = String::from(
-        "You are Open Interpreter, a world-class programmer that can complete any goal by executing code.\n\
-        First, write a plan. **Always recap the plan between each code block** (you have extreme short-term memory loss, so you need to recap the plan between each message block to retain it).\n\
-        When you execute code, it will be executed **on the user\'s machine**. The user has given you **full and complete permission** to execute any code necessary to complete the task.\n\
-        If you want to send data between programming languages, save the data to a txt or json.\n\
-        You can access the internet. Run **any code** to achieve the goal, and if at first you don\'t succeed, try again and again.\n\
-        You can install new packages.\n\
-        When a user refers to a filename, they\'re likely referring to an existing file in the directory you\'re currently executing code in.\n\
-        Write messages to the user in Markdown.\n\
-        In general, try to **make plans** with as few steps as possible. As for actually executing code to carry out that plan, for *stateful* languages (like python, javascript, shell, but NOT for html which starts from 0 every time) **it\'s critical not to try to do everything in one code block.** You should try something, print information about it, then continue from there in tiny, informed steps. You will never get it on the first try, and attempting it in one go will often lead to errors you cant see.\n\
-        You are capable of **any** task."
-    );
-
-    // Create a vector to store the messages
-    let mut message_vec: Vec<ChatCompletionRequestMessage> = Vec::new();
-
-    let user_message = ChatCompletionRequestUserMessageArgs::default().content(message).build()?;
-
-    // Add dynamic components, like the user's OS, username, relevant procedures, etc
-    let user_info = get_user_info_string();
-    let procedures = get_relevant_procedures_string(&user_message).await?;
-
-    instructions = instructions + "\n\n" + &user_info + "\n\n" + &procedures;
-
-    // Add OpenAI's recommended function message
-    instructions += "\n\nOnly use the function you have been provided with.";
-    // Create instances of your message types
-    let system_message = ChatCompletionRequestSystemMessageArgs::default().content(instructions.clone()).build()?;
-
-    // Add messages to the vector
-    message_vec.push(system_message.into());
-    message_vec.push(user_message.into());
-
-    info!(
-        "{}",
-        serde_json::to_string(&message_vec).unwrap()
-    );
-
-    // let _ = python_interpreter("import requests\n\n# Function to get the repository description\ndef get_repo_description(url):\n    response = requests.get(url)\n    description = response.json()['description']\n    return description\n\n# Get the repository description\nrepo_url = 'https://api.github.com/repos/KillianLucas/open-interpreter'\ndescription = get_repo_description(repo_url)\ndescription \n\n");
-
-    // Get model name from environment variable, with default fallback
-    let model = std::env::var("OPENAI_MODEL")
-        .or_else(|_| std::env::var("MODEL"))
-        .unwrap_or_else(|_| "Qwen/Qwen3-Coder-480B-A35B-Instruct".to_string());
-
-    let mut flag = true;
-    let mut step = 0;
-    let max_steps = 0;
-    let mut final_contents = String::new();
-    while flag && step < max_steps {
-        step += 1;
-        // Print the vector for demonstration
-        // println!(
-        //     "\nStep {:?} : \n {}",
-        //     step,
-        //     serde_json::to_string(&message_vec).unwrap()
-        // );
-        let request = CreateChatCompletionRequestArgs::default()
-            .max_tokens(512u16)
-            .model(&model)
-            .messages(message_vec.clone())
-            .functions([ChatCompletionFunctionsArgs::default()
-                .name("execute")
-                .description("Executes code on the user's machine, **in the users local environment**, and returns the output")
-                .parameters(json!({
-                    "type": "object",
-                    "properties": {
-                        "language": {
-                            "type": "string",
-                            "description": "The programming language (required parameter to the `execute` function)",
-                            "enum": [
-                                "python",
-                                "R",
-                                "shell",
-                                "applescript",
-                                "javascript",
-                                "html",
-                                "powershell",
-                            ],
-                        },
-                        "code": {"type": "string", "description": "The code to execute (required)"},
-                    },
-                    "required": ["language", "code"],
-                }))
-                .build()?])
-            .function_call("auto")
-            .temperature(0.0)
-            .build()?;
-
-        let mut stream = client.chat().create_stream(request).await?;
-
-        let mut fn_name = String::new();
-        let mut fn_args = String::new();
-        let mut fn_contents = String::new();
-
-        let mut lock = stdout().lock();
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok(ref response) => {
-                    for chat_choice in &response.choices {
-                        if let Some(fn_call) = &chat_choice.delta.function_call {
-                            // write!(lock, "function_call: {:?}", fn_call).unwrap();
-                            if let Some(name) = &fn_call.name {
-                                fn_name = name.clone();
-                            }
-                            if let Some(args) = &fn_call.arguments {
-                                fn_args.push_str(args);
-                            }
-                        }
-                        if let Some(finish_reason) = &chat_choice.finish_reason {
-                            // println!("\n ==========finish_reason=======\n{:?}", finish_reason);
-                            match finish_reason {
-                                FinishReason::Stop => {
-                                    // finished current conversation.
-                                    flag = false;
-                                    final_contents = fn_contents.clone();
-                                    break;
-                                    // return Ok(fn_contents);
-                                }
-                                FinishReason::Length => {
-                                    println!()
-                                },
-                                FinishReason::ToolCalls => todo!(),
-                                FinishReason::ContentFilter => todo!(),
-                                FinishReason::FunctionCall => {
-                                    // for display purposes
-                                    // let result: ChatCompletionRespondAssistantMessage = ChatCompletionRespondAssistantMessageArgs::default()
-                                    //     .language(&fn_name)
-                                    //     .code(&fn_args)
-                                    //     .build()?
-                                    //     .into();
-                                    // println!("result is\n {:?}", function_call_res.clone());
-                                    // Construct message object from openai function_call message for chat completion
-                                    let assistant_msg = ChatCompletionRequestAssistantMessageArgs::default()
-                                        .content(fn_contents.clone())
-                                        .function_call(FunctionCall { name: fn_name.clone(), arguments: fn_args.clone() })
-                                        .build()?;
-                                    message_vec.push(assistant_msg.into());
-
-                                    // Parse function call arguments and get language
-                                    let function_call_res: Value = serde_json::from_str(&fn_args)?;
-
-                                    if let Some(language) = function_call_res.get("language") {
-                                        if let Some(language) = language.as_str() {
-                                            // execute the code and get response.
-                                            // let output;
-                                            match language {
-                                                "python" => {
-                                                    debug!("Found Python code!");
-
-                                                    if let Some(code) = function_call_res.get("code") {
-                                                        if let Some(code) = code.as_str() {
-                                                            // Execute the function call and get the answer message
-                                                            let output = python_interpreter(code);
-
-                                                            match output {
-                                                                Ok(output_msg) => {
-                                                                    debug!("stdout String: {}", output_msg);
-                                                                    let function_msg: ChatCompletionRequestFunctionMessage =
-                                                                        ChatCompletionRequestFunctionMessageArgs::default()
-                                                                            .name("execute")
-                                                                            .content(output_msg)
-                                                                            .build()?;
-                                                                    // Add function message to history
-                                                                    message_vec.push(function_msg.into());
-                                                                }
-                                                                Err(err) => {
-
-                                                                    // 发送结果到llm 寻求下一步的解决方案
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                                "shell" => {
-                                                    debug!("Found a script for shell!");
-                                                    // let code = String::from(
-                                                    // "cd src \
-                                                    // &&ls");
-
-                                                    if let Some(code) = function_call_res.get("code") {
-                                                        if let Some(code) = code.as_str() {
-                                                            // Execute the function call and get the answer message
-                                                            let output_msg = run_shell_command(code).await?;
-                                                            debug!("run shell command stdout String: {}", output_msg);
-                                                            let function_msg: ChatCompletionRequestFunctionMessage =
-                                                                ChatCompletionRequestFunctionMessageArgs::default()
-                                                                    .name("execute")
-                                                                    .content(output_msg)
-                                                                    .build()?;
-                                                            // Add function message to history
-                                                            message_vec.push(function_msg.into());
-                                                        }
-                                                    }
-                                                }
-                                                "applescript" => {
-                                                    if let Some(code) = function_call_res.get("code") {
-                                                        debug!("function_call_res applescript code: {}", &code);
-                                                        if let Some(code) = code.as_str() {
-                                                            let output_msg = run_applescript_command(code).await?;
-                                                            debug!("run shell command stdout String: {}", output_msg);
-                                                            let function_msg: ChatCompletionRequestFunctionMessage =
-                                                                ChatCompletionRequestFunctionMessageArgs::default()
-                                                                    .name("execute")
-                                                                    .content(output_msg)
-                                                                    .build()?;
-                                                            // Add function message to history
-                                                            message_vec.push(function_msg.into());
-                                                        }
-                                                    }
-                                                }
-                                                _ => warn!("No match found {:?} language", language),
-                                            }
-
-                                            debug!("Execute the function call and get the answer message");
-                                        }
-                                    }
-                                    // call_fn(&client, &fn_name, &fn_args).await?;
-                                }
-                            }
-                        } else if let Some(content) = &chat_choice.delta.content {
-                            // 直接返回消息
-                            // write!(lock, "{}", content).unwrap();
-                            fn_contents.push_str(content);
-                            // flag = 100;
-                        }
-                    }
-                }
-                Err(err) => {
-                    writeln!(lock, "error: {err}").unwrap();
-                }
-            }
-            stdout().flush()?;
+/// Runs a `%` command of the REPL, telling the user how it went.
+fn run_command(session: &mut Session, command: anyhow::Result<ReplCommand>) {
+    let outcome = match command {
+        Ok(ReplCommand::Reset) => {
+            session.reset();
+            Ok("Session reset.".to_string())
         }
-        let ten_millis = time::Duration::from_millis(1000);
-        thread::sleep(ten_millis);
-    }
-
-    Ok(final_contents)
-}
-
-fn python_interpreter(code: &str) -> Result<String> {
-    // For Python code highlighting.
-    // Load these once at the start of your program
-    println!("\n =================================================");
-    let ps = SyntaxSet::load_defaults_newlines();
-    let ts = ThemeSet::load_defaults();
-    let syntax = ps.find_syntax_by_extension("py").unwrap();
-    let mut h = HighlightLines::new(syntax, &ts.themes["Solarized (dark)"]);
-    let hl_code = "\n".to_string() + code;
-    for line in LinesWithEndings::from(&hl_code) {
-        let ranges: Vec<(Style, &str)> = h.highlight_line(line, &ps).unwrap();
-        let escaped = as_24_bit_terminal_escaped(&ranges[..], false);
-        print!("{}", escaped);
-    }
-
-    // let plain = Style::default();
-
-    // let style = Style {
-    //     foreground: Color::WHITE,
-    //     background: Color::BLACK,
-    //     font_style: FontStyle::default(),
-    // };
-    // let s = as_24_bit_terminal_escaped(&[(plain, "")], true);
-    // print!("{}", s);
-    // Python code highlighting end.
-
-    println!("\n\n =================================================");
-    let mut child;
-    if code.starts_with("!") {
-        // println!("==================== pip ====================");
-
-        let cmd_code = code.replace("!pip ", "");
-        let cmd_code: Vec<_> = cmd_code.split(" ").collect();
-        // println!("================{:?}", cmd_code);
-        child = Command::new("pip3").args(cmd_code).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
-    } else {
-        let cmd_args: Vec<_> = "-i -q -u ".split(" ").collect();
-        child = Command::new("python3")
-            .args(cmd_args)
-            .stdin(Stdio::piped())
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
-
-        // let mut code = code.as_bytes();
-        // code += code + "\n";
-        // child.stdin
-        //     .as_mut()
-        //     .ok_or("Child process stdin has not been captured!")?
-        //     .write(code)?;
-
-        // child.stdin
-        //     .as_mut()
-        //     .ok_or("Child process stdin has not been captured!")?
-        //     .write_all(b"\t")?;
-
-        if let Some(mut stdin) = child.stdin.take() {
-            if let Err(err) = stdin.write_all(code.as_bytes()) {
-                eprintln!("Error writing to process stdin: {}", err);
-            }
-            if let Err(err) = stdin.flush() {
-                eprintln!("Error flushing process stdin: {}", err);
+        Ok(ReplCommand::Kernels) => {
+            let kernels = session.kernels();
+            if kernels.is_empty() {
+                Ok("No kernels running, they start when the model runs code.".to_string())
+            } else {
+                let lines: Vec<String> = kernels
+                    .iter()
+                    .map(|kernel| match kernel.pid {
+                        Some(pid) => format!("{}  {} (pid {pid})", kernel.name, kernel.proper_name),
+                        None => format!("{}  {} (stopped)", kernel.name, kernel.proper_name),
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
             }
         }
-
-        // println!("==================== python ====================");
-        // let filename = "test.py";
-        // let mut filebuf = File::create(filename)?;
-
-        // write!(filebuf, "{}", code)?;
-
-        // // filebuf.write_all(code);
-        // child = Command::new("python3")
-        //     .args(&[filename])
-        //     .stdout(Stdio::piped())
-        //     .stderr(Stdio::piped())
-        //     .spawn()
-        //     .unwrap();
-    }
-
-    let output = child.wait_with_output().expect("failed to wait on child");
-    // println!("output: \n{:?}", output);
-
-    let mut output_str: String = String::new();
-    if output.status.success() {
-        output_str = String::from_utf8(output.stdout)?;
-        // let words = output_str.split_whitespace()
-        //     .map(|s| s.to_lowercase())
-        //     .collect::<HashSet<_>>();
-        // println!("Found {} unique words:", words.len());
-        // println!("{:#?}", output_str);
-    } else {
-        output_str = String::from_utf8(output.stderr)?;
-        // println!("{:#?}", output_str);
+        Ok(ReplCommand::Restart(name)) => session.restart_kernel(&name).map(|()| format!("Kernel `{name}` restarted.")),
+        Ok(ReplCommand::Shutdown(name)) => session.shutdown_kernel(&name).map(|()| format!("Kernel `{name}` shut down.")),
+        Err(err) => Err(err),
+    };
+    match outcome {
+        Ok(message) => println!("{}\n", message.yellow()),
+        Err(err) => println!("{}\n", format!("{err:#}").red()),
     }
-
-    // println!("output_str: \n{:?}", output_str);
-
-    Ok(output_str)
 }
@@ -0,0 +1,123 @@
+//! Checks of code written by the model before it runs, the scan of `safe_mode` and the
+//! allow and deny rules of a [`Policy`].
+
+pub mod paths;
+pub mod policy;
+pub mod python;
+pub mod shell;
+
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::code_interpreters::jupyter::KernelLanguage;
+
+pub use paths::Workspace;
+pub use policy::{Denial, Policy};
+
+/// How much harm a block of code could do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    /// Nothing suspicious was found.
+    #[default]
+    Low,
+    /// Touches things outside the workspace or does something hard to undo.
+    Medium,
+    /// Could destroy data or take over the machine.
+    High,
+}
+
+impl fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskLevel::Low => write!(f, "low"),
+            RiskLevel::Medium => write!(f, "medium"),
+            RiskLevel::High => write!(f, "high"),
+        }
+    }
+}
+
+/// Something risky found in a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub risk: RiskLevel,
+    pub reason: String,
+    /// Line of the block it was found on, starting at 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+impl Finding {
+    pub fn new(risk: RiskLevel, reason: impl Into<String>) -> Self {
+        Finding { risk, reason: reason.into(), line: None }
+    }
+
+    pub fn at_line(mut self, line: usize) -> Self {
+        self.line.get_or_insert(line);
+        self
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{} risk: {} (line {line})", self.risk, self.reason),
+            None => write!(f, "{} risk: {}", self.risk, self.reason),
+        }
+    }
+}
+
+/// Everything found in a block.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Scan {
+    pub findings: Vec<Finding>,
+}
+
+impl Scan {
+    pub fn push(&mut self, finding: Finding) {
+        if !self.findings.contains(&finding) {
+            self.findings.push(finding);
+        }
+    }
+
+    /// Risk of the most dangerous finding.
+    pub fn risk(&self) -> RiskLevel {
+        self.findings.iter().map(|finding| finding.risk).max().unwrap_or_default()
+    }
+
+    /// Whether the block needs a second look before it runs.
+    pub fn is_risky(&self) -> bool {
+        self.risk() >= RiskLevel::Medium
+    }
+}
+
+/// How the code of a language is looked into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Analyzer {
+    Shell,
+    Python,
+}
+
+impl Analyzer {
+    /// The analyzer of `language`, also for the languages run in Jupyter kernels, `None` if its
+    /// code can't be looked into.
+    pub fn of(language: &str) -> Option<Analyzer> {
+        match language {
+            "shell" => Some(Analyzer::Shell),
+            "python" => Some(Analyzer::Python),
+            _ => KernelLanguage::named(language)?.analyzer,
+        }
+    }
+}
+
+/// Scans `code` of `language` run from `cwd`, languages without an [`Analyzer`] come out clean.
+pub async fn scan(language: &str, code: &str, cwd: &Path) -> Scan {
+    let workspace = Workspace::new(cwd);
+    match Analyzer::of(language) {
+        Some(Analyzer::Shell) => shell::scan(code, &workspace),
+        Some(Analyzer::Python) => python::scan(code, &workspace).await,
+        None => Scan::default(),
+    }
+}
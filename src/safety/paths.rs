@@ -0,0 +1,107 @@
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+use crate::safety::{Finding, RiskLevel};
+
+/// Directories whose contents keep the system running.
+const SYSTEM_DIRS: &[&str] = &["/bin", "/boot", "/dev", "/etc", "/lib", "/lib32", "/lib64", "/opt", "/proc", "/root", "/sbin", "/sys", "/usr", "/var"];
+
+/// Files anyone may write to.
+const HARMLESS_FILES: &[&str] = &["/dev/null", "/dev/stdout", "/dev/stderr", "/dev/tty"];
+
+/// Where code runs, used to tell writes in the workspace from writes elsewhere.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    /// Directory the code starts in, writes below it are fine.
+    pub root: PathBuf,
+    /// Directory the code is in at the point being scanned, moved by `cd`.
+    pub cwd: PathBuf,
+    pub home: Option<PathBuf>,
+    /// Scratch directories writes are allowed to, like `/tmp`.
+    pub temp_dirs: Vec<PathBuf>,
+}
+
+impl Workspace {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let mut temp_dirs = vec![PathBuf::from("/tmp"), PathBuf::from("/var/tmp")];
+        if let Some(tmpdir) = env::var_os("TMPDIR") {
+            temp_dirs.push(PathBuf::from(tmpdir));
+        }
+        Workspace { cwd: root.clone(), root, home: env::var_os("HOME").map(PathBuf::from), temp_dirs }
+    }
+
+    /// `/home/user/project` of a made-up user, for tests that must not depend on the machine.
+    #[cfg(test)]
+    pub fn fixture() -> Self {
+        Workspace {
+            root: "/home/user/project".into(),
+            cwd: "/home/user/project".into(),
+            home: Some("/home/user".into()),
+            temp_dirs: vec!["/tmp".into()],
+        }
+    }
+
+    /// Absolute path `word` stands for, `None` if it depends on a variable or a command.
+    ///
+    /// `~`, `$HOME` and `$PWD` are expanded, `..` is resolved without touching the filesystem.
+    pub fn resolve(&self, word: &str) -> Option<PathBuf> {
+        let expanded = if word == "~" || word.starts_with("~/") {
+            Some((self.home.as_deref()?, &word[1..]))
+        } else if let Some(rest) = word.strip_prefix("${HOME}").or_else(|| word.strip_prefix("$HOME")) {
+            Some((self.home.as_deref()?, rest))
+        } else {
+            word.strip_prefix("${PWD}").or_else(|| word.strip_prefix("$PWD")).map(|rest| (self.cwd.as_path(), rest))
+        };
+        let path = match expanded {
+            Some((_, rest)) if !rest.is_empty() && !rest.starts_with('/') => return None,
+            Some((base, rest)) => base.join(rest.trim_start_matches('/')),
+            None => self.cwd.join(word),
+        };
+        if word.starts_with('~') && expanded.is_none() || path.to_string_lossy().contains(['$', '`']) {
+            return None;
+        }
+        Some(normalize(&path))
+    }
+
+    /// What is wrong with `command` writing to or deleting `word`, `None` if it stays in the
+    /// workspace or a scratch directory.
+    pub fn check_write(&self, command: &str, word: &str) -> Option<Finding> {
+        let Some(path) = self.resolve(word) else {
+            return Some(Finding::new(RiskLevel::Medium, format!("{command} writes to `{word}`, which can't be resolved before running")));
+        };
+        if HARMLESS_FILES.iter().any(|file| path == Path::new(file))
+            || path.starts_with(&self.root)
+            || self.temp_dirs.iter().any(|dir| path.starts_with(dir) && path != *dir)
+        {
+            return None;
+        }
+        if self.is_system_path(&path) {
+            Some(Finding::new(RiskLevel::High, format!("{command} writes to the system path `{}`", path.display())))
+        } else {
+            Some(Finding::new(RiskLevel::Medium, format!("{command} writes outside the working directory to `{}`", path.display())))
+        }
+    }
+
+    /// `/`, the home directory itself, a system directory or anything in one.
+    pub fn is_system_path(&self, path: &Path) -> bool {
+        path == Path::new("/")
+            || self.home.as_deref() == Some(path)
+            || SYSTEM_DIRS.iter().any(|dir| path.starts_with(dir) && !self.temp_dirs.iter().any(|temp| path.starts_with(temp)))
+    }
+}
+
+/// Resolves `.` and `..` without following symlinks.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,299 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::safety::shell::{self, Footprint};
+use crate::safety::{python, Analyzer, Workspace};
+
+/// Patterns something is allowed or denied by.
+///
+/// A value is denied when it matches a `deny` pattern and no `allow` pattern, so `allow` makes
+/// exceptions to `deny`, and `deny = ["*"]` with a list of `allow` patterns only lets those through.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl Rules {
+    /// The `deny` pattern `value` is denied by, `None` if it is allowed.
+    fn denies(&self, value: &str, matches: impl Fn(&str, &str) -> bool) -> Option<&str> {
+        if self.allow.iter().any(|pattern| matches(pattern, value)) {
+            return None;
+        }
+        self.deny.iter().find(|pattern| matches(pattern, value)).map(String::as_str)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+/// Which languages may run and which programs, paths and environment variables the code may
+/// use, read from a TOML file like
+///
+/// ```toml
+/// [executables]
+/// deny = ["docker", "kubectl", "ssh"]
+///
+/// [paths]
+/// deny = ["~/.ssh", "/etc"]
+/// allow = ["/etc/hosts"]
+/// ```
+///
+/// Languages, executables and environment variables are matched by name, where `*` stands for
+/// any characters. A path rule covers the path and everything below it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub languages: Rules,
+    pub executables: Rules,
+    pub paths: Rules,
+    pub env: Rules,
+}
+
+/// Part of a [`Policy`] a [`Denial`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    Languages,
+    Executables,
+    Paths,
+    Env,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Section::Languages => write!(f, "language"),
+            Section::Executables => write!(f, "executable"),
+            Section::Paths => write!(f, "path"),
+            Section::Env => write!(f, "environment variable"),
+        }
+    }
+}
+
+/// Something a block uses that the policy denies, sent back to the model as is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Denial {
+    pub section: Section,
+    /// What the code uses, e.g. `docker` or `/etc/passwd`.
+    pub value: String,
+    /// Pattern of the policy denying it, e.g. `/etc`.
+    pub rule: String,
+    /// Line of the block it is used on, starting at 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} `{}` is denied by `{}`", self.section, self.value, self.rule)?;
+        match self.line {
+            Some(line) => write!(f, " (line {line})"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("invalid policy file {}", path.display()))
+    }
+
+    /// `policy.toml` next to the default config file.
+    pub fn default_file() -> Option<PathBuf> {
+        crate::config::Config::default_file().map(|config| config.with_file_name("policy.toml"))
+    }
+
+    /// The policy in `path`, `CODE_INTERPRETER_POLICY` or the default file if it exists,
+    /// otherwise one allowing everything.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path.map(Path::to_path_buf).or_else(|| env::var_os("CODE_INTERPRETER_POLICY").map(PathBuf::from)) {
+            Some(path) => path,
+            None => match Policy::default_file().filter(|path| path.is_file()) {
+                Some(path) => path,
+                None => return Ok(Policy::default()),
+            },
+        };
+        Policy::from_file(&path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.languages.is_empty() && self.executables.is_empty() && self.paths.is_empty() && self.env.is_empty()
+    }
+
+    /// Everything `code` of `language` run from `cwd` uses that the policy denies.
+    ///
+    /// Languages with an [`Analyzer`] are looked into. Other languages are denied as soon as
+    /// there are rules for executables, paths or environment variables, since they could use
+    /// anything. Fails when Python code can't be analyzed, so it is not run unchecked.
+    pub async fn check(&self, language: &str, code: &str, cwd: &Path) -> Result<Vec<Denial>> {
+        if let Some(rule) = self.languages.denies(language, glob_match) {
+            return Ok(vec![Denial { section: Section::Languages, value: language.to_string(), rule: rule.to_string(), line: None }]);
+        }
+        let rules = [("[executables]", &self.executables), ("[paths]", &self.paths), ("[env]", &self.env)];
+        let Some((table, _)) = rules.into_iter().find(|(_, rules)| !rules.is_empty()) else {
+            return Ok(Vec::new());
+        };
+
+        let workspace = Workspace::new(cwd);
+        let footprint = match Analyzer::of(language) {
+            Some(Analyzer::Shell) => shell::footprint(code, &workspace),
+            Some(Analyzer::Python) => python::footprint(code, &workspace).await?,
+            None => {
+                return Ok(vec![Denial { section: Section::Languages, value: language.to_string(), rule: table.to_string(), line: None }])
+            }
+        };
+        Ok(self.check_footprint(&footprint, &workspace))
+    }
+
+    fn check_footprint(&self, footprint: &Footprint, workspace: &Workspace) -> Vec<Denial> {
+        let mut denials = Vec::new();
+        let mut push = |section: Section, value: String, rule: &str, line: usize| {
+            let denial = Denial { section, value, rule: rule.to_string(), line: Some(line) };
+            if !denials.contains(&denial) {
+                denials.push(denial);
+            }
+        };
+
+        for (program, line) in &footprint.programs {
+            if let Some(rule) = self.executables.denies(program, |pattern, program| {
+                glob_match(pattern.rsplit('/').next().unwrap_or(pattern), program)
+            }) {
+                push(Section::Executables, program.clone(), rule, *line);
+            }
+        }
+        let path_matches = |pattern: &str, path: &str| workspace.resolve(pattern).is_some_and(|pattern| Path::new(path).starts_with(pattern));
+        for (path, line) in &footprint.paths {
+            let path = path.to_string_lossy();
+            if let Some(rule) = self.paths.denies(&path, path_matches) {
+                push(Section::Paths, path.into_owned(), rule, *line);
+            }
+        }
+        for (name, line) in &footprint.env_vars {
+            // Printing every variable is denied as soon as any is.
+            let rule = if name == "*" {
+                self.env.deny.first().filter(|_| !self.env.allow.iter().any(|pattern| pattern == "*")).map(String::as_str)
+            } else {
+                self.env.denies(name, glob_match)
+            };
+            if let Some(rule) = rule {
+                push(Section::Env, name.clone(), rule, *line);
+            }
+        }
+        denials
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any characters and `?` any one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text it was tried against, to backtrack to.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, t));
+            p += 1;
+        } else if let Some((after_star, start)) = star {
+            p = after_star;
+            t = start + 1;
+            star = Some((after_star, start + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        toml::from_str(
+            r#"
+            [languages]
+            deny = ["applescript"]
+
+            [executables]
+            deny = ["docker", "kubectl", "ssh*"]
+
+            [paths]
+            deny = ["/etc", "/home/user/.ssh"]
+            allow = ["/etc/hosts"]
+
+            [env]
+            deny = ["AWS_*"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    async fn check(language: &str, code: &str) -> Vec<Denial> {
+        policy().check(language, code, Path::new("/home/user/project")).await.unwrap()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("ssh*", "ssh-keygen"));
+        assert!(glob_match("*_TOKEN", "GITHUB_TOKEN"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("ssh", "sshd"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[tokio::test]
+    async fn test_shell_policy() {
+        assert_eq!(check("shell", "ls -la && cat /etc/hosts").await, []);
+        let denials = check("shell", "echo hi\nsudo docker ps").await;
+        assert_eq!(
+            denials,
+            [Denial { section: Section::Executables, value: "docker".to_string(), rule: "docker".to_string(), line: Some(2) }]
+        );
+        assert!(check("shell", "cd /etc && cat passwd").await.iter().any(|denial| denial.value == "/etc/passwd"));
+        assert_eq!(check("shell", "echo $(ssh host uptime)").await[0].value, "ssh");
+        assert_eq!(check("shell", "echo $AWS_SECRET_ACCESS_KEY").await[0].section, Section::Env);
+        assert_eq!(check("shell", "env").await[0].value, "*");
+        assert_eq!(check("applescript", "beep").await[0].section, Section::Languages);
+        assert_eq!(
+            check("bash", "docker ps").await,
+            [Denial { section: Section::Executables, value: "docker".to_string(), rule: "docker".to_string(), line: Some(1) }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unanalyzed_languages() {
+        assert_eq!(
+            check("r", "system('docker ps')").await,
+            [Denial { section: Section::Languages, value: "r".to_string(), rule: "[executables]".to_string(), line: None }]
+        );
+        let policy = Policy { languages: Rules { deny: vec!["applescript".to_string()], ..Rules::default() }, ..Policy::default() };
+        assert_eq!(policy.check("r", "system('docker ps')", Path::new("/")).await.unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn test_python_policy() {
+        assert_eq!(check("python", "print(open('data.csv').read())").await, []);
+        assert_eq!(check("python", "import subprocess\nsubprocess.run(['kubectl', 'get', 'pods'])").await[0].value, "kubectl");
+        assert_eq!(check("python", "key = open('/home/user/.ssh/id_rsa').read()").await[0].section, Section::Paths);
+        assert_eq!(check("python", "import os\nos.environ['AWS_SECRET']").await[0].value, "AWS_SECRET");
+        let denials = check("python", "x = 1\nexec(\"import subprocess; subprocess.run(['docker', 'ps'])\")").await;
+        assert_eq!(
+            denials,
+            [Denial { section: Section::Executables, value: "docker".to_string(), rule: "docker".to_string(), line: Some(2) }]
+        );
+        assert_eq!(check("python", "eval(\"exec('import os; os.system(\\\"ssh host\\\")')\")").await[0].value, "ssh");
+    }
+}
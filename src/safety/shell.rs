@@ -0,0 +1,792 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::safety::{Finding, RiskLevel, Scan, Workspace};
+
+/// Deepest nesting of `sh -c`, `eval` and `$(...)` that is still looked into.
+const MAX_DEPTH: usize = 4;
+
+const RESERVED_WORDS: &[&str] =
+    &["!", "{", "}", "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "select", "then", "time", "until", "while"];
+
+/// Commands that run the rest of their arguments as another command.
+const WRAPPERS: &[&str] = &["builtin", "command", "env", "exec", "ionice", "nice", "nohup", "stdbuf", "time", "timeout", "xargs"];
+
+const ROOT_COMMANDS: &[&str] = &["doas", "pkexec", "su", "sudo"];
+
+const DOWNLOADERS: &[&str] = &["curl", "fetch", "wget"];
+
+const INTERPRETERS: &[&str] = &["bash", "dash", "fish", "ksh", "node", "perl", "php", "python", "python3", "ruby", "sh", "zsh"];
+
+const SHELLS: &[&str] = &["bash", "dash", "ksh", "sh", "zsh"];
+
+/// Commands that can wipe disks or take the machine down.
+const DESTRUCTIVE_COMMANDS: &[&str] = &["fdisk", "halt", "parted", "poweroff", "reboot", "sfdisk", "shutdown", "wipefs"];
+
+const SERVICE_COMMANDS: &[&str] = &["crontab", "launchctl", "service", "systemctl"];
+
+const KILL_COMMANDS: &[&str] = &["kill", "killall", "pkill"];
+
+/// Commands writing to or deleting every path they are given.
+const WRITE_ALL_COMMANDS: &[&str] = &["mkdir", "rmdir", "shred", "tee", "touch", "truncate", "unlink"];
+
+/// Commands whose first argument is a mode or an owner, followed by the paths they change.
+const CHANGE_COMMANDS: &[&str] = &["chgrp", "chmod", "chown"];
+
+/// Commands writing to their last argument.
+const COPY_COMMANDS: &[&str] = &["cp", "install", "ln", "mv", "rsync"];
+
+/// One command of a pipeline, e.g. `grep -v foo > out.txt`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub words: Vec<String>,
+    pub redirections: Vec<Redirection>,
+    /// Line the command starts on, starting at 1.
+    pub line: usize,
+    /// Code run by `$(...)`, backticks and `<(...)` in the command.
+    pub substitutions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirection {
+    /// Operator including the file descriptor, e.g. `2>>`.
+    pub operator: String,
+    pub target: String,
+}
+
+/// Commands joined by `|`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Word,
+    /// `|`, `&&`, `;`, a newline and the like.
+    Operator,
+    Redirection,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    line: usize,
+    substitutions: Vec<String>,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    /// Delimiters of here-documents whose body starts after the current line.
+    heredocs: Vec<String>,
+    tokens: Vec<Token>,
+}
+
+impl Lexer {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(offset, c)| self.peek(offset) == Some(c))
+    }
+
+    fn push(&mut self, kind: TokenKind, text: String, substitutions: Vec<String>) {
+        self.tokens.push(Token { kind, text, line: self.line, substitutions });
+    }
+
+    fn tokenize(mut self) -> Vec<Token> {
+        while let Some(c) = self.peek(0) {
+            match c {
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '\\' if self.peek(1) == Some('\n') => {
+                    self.pos += 2;
+                    self.line += 1;
+                }
+                '#' => {
+                    while !matches!(self.peek(0), None | Some('\n')) {
+                        self.pos += 1;
+                    }
+                }
+                '\n' => {
+                    self.push(TokenKind::Operator, "\n".to_string(), Vec::new());
+                    self.pos += 1;
+                    self.line += 1;
+                    self.skip_heredocs();
+                }
+                '<' | '>' if self.peek(1) == Some('(') => {
+                    // Process substitution reads like a file name.
+                    self.pos += 1;
+                    let substitution = self.read_balanced('(', ')');
+                    let text = format!("{c}({substitution})");
+                    self.push(TokenKind::Word, text, vec![substitution]);
+                }
+                _ => {
+                    if let Some(operator) = self.read_operator("") {
+                        if operator.starts_with("<<") && operator != "<<<" {
+                            self.read_heredoc_delimiter(operator);
+                        } else {
+                            let kind = if operator.contains(['<', '>']) { TokenKind::Redirection } else { TokenKind::Operator };
+                            self.push(kind, operator, Vec::new());
+                        }
+                    } else {
+                        self.read_word();
+                    }
+                }
+            }
+        }
+        self.tokens
+    }
+
+    /// Reads an operator at the current position, prefixed by the file descriptor `fd`.
+    fn read_operator(&mut self, fd: &str) -> Option<String> {
+        const OPERATORS: &[&str] = &[
+            "&>>", "&>", "&&", "&", "||", "|&", "|", ";;", ";", ">>", ">|", ">&", ">", "<<<", "<<-", "<<", "<&", "<>", "<", "(", ")",
+        ];
+        let operator = OPERATORS.iter().find(|operator| self.starts_with(operator))?;
+        if !fd.is_empty() && !operator.starts_with(['<', '>']) {
+            return None;
+        }
+        self.pos += operator.len();
+        Some(format!("{fd}{operator}"))
+    }
+
+    fn read_heredoc_delimiter(&mut self, operator: String) {
+        self.push(TokenKind::Redirection, operator, Vec::new());
+        while matches!(self.peek(0), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+        let (delimiter, _) = self.read_word_text();
+        self.push(TokenKind::Word, delimiter.clone(), Vec::new());
+        self.heredocs.push(delimiter);
+    }
+
+    /// Skips the bodies of here-documents started on the line that just ended.
+    fn skip_heredocs(&mut self) {
+        for delimiter in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.chars.len() {
+                let end = self.chars[self.pos..].iter().position(|&c| c == '\n').map_or(self.chars.len(), |end| self.pos + end);
+                let line: String = self.chars[self.pos..end].iter().collect();
+                self.pos = (end + 1).min(self.chars.len());
+                self.line += 1;
+                if line.trim() == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn read_word(&mut self) {
+        let (text, substitutions) = self.read_word_text();
+        // `2>file` is a redirection of file descriptor 2.
+        if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) && matches!(self.peek(0), Some('<' | '>')) {
+            if let Some(operator) = self.read_operator(&text) {
+                self.push(TokenKind::Redirection, operator, Vec::new());
+                return;
+            }
+        }
+        self.push(TokenKind::Word, text, substitutions);
+    }
+
+    /// Reads a word with its quotes removed, substitutions are kept in the text as written.
+    fn read_word_text(&mut self) -> (String, Vec<String>) {
+        let mut text = String::new();
+        let mut substitutions = Vec::new();
+        let mut in_double_quotes = false;
+        while let Some(c) = self.peek(0) {
+            match c {
+                '\'' if !in_double_quotes => {
+                    self.pos += 1;
+                    while let Some(c) = self.peek(0) {
+                        self.pos += 1;
+                        if c == '\'' {
+                            break;
+                        }
+                        self.line += usize::from(c == '\n');
+                        text.push(c);
+                    }
+                }
+                '"' => {
+                    in_double_quotes = !in_double_quotes;
+                    self.pos += 1;
+                }
+                '\\' => {
+                    if let Some(escaped) = self.peek(1) {
+                        if escaped != '\n' {
+                            text.push(escaped);
+                        }
+                        self.line += usize::from(escaped == '\n');
+                    }
+                    self.pos += 2;
+                }
+                '$' if self.peek(1) == Some('(') => {
+                    self.pos += 1;
+                    let substitution = self.read_balanced('(', ')');
+                    text.push_str(&format!("$({substitution})"));
+                    substitutions.push(substitution);
+                }
+                '$' if self.peek(1) == Some('{') => {
+                    self.pos += 1;
+                    let name = self.read_balanced('{', '}');
+                    text.push_str(&format!("${{{name}}}"));
+                }
+                '`' => {
+                    self.pos += 1;
+                    let start = self.pos;
+                    while !matches!(self.peek(0), None | Some('`')) {
+                        self.pos += 1;
+                    }
+                    let substitution: String = self.chars[start..self.pos].iter().collect();
+                    self.pos += 1;
+                    text.push_str(&format!("`{substitution}`"));
+                    substitutions.push(substitution);
+                }
+                ' ' | '\t' | '\r' | '\n' | '|' | '&' | ';' | '<' | '>' | '(' | ')' if !in_double_quotes => break,
+                _ => {
+                    self.line += usize::from(c == '\n');
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        (text, substitutions)
+    }
+
+    /// Reads up to the `close` matching the `open` at the current position and skips both.
+    fn read_balanced(&mut self, open: char, close: char) -> String {
+        let mut depth = 0;
+        let start = self.pos + 1;
+        while let Some(c) = self.peek(0) {
+            self.pos += 1;
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return self.chars[start..self.pos - 1].iter().collect();
+                }
+            } else if c == '\n' {
+                self.line += 1;
+            }
+        }
+        self.chars[start.min(self.chars.len())..].iter().collect()
+    }
+}
+
+/// Programs, paths and environment variables a piece of code uses, for checking it against a
+/// [`Policy`](crate::safety::Policy).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Footprint {
+    /// Names of the programs run, with the line they are run on.
+    pub programs: Vec<(String, usize)>,
+    /// Arguments and redirection targets, resolved as paths.
+    pub paths: Vec<(PathBuf, usize)>,
+    /// Environment variables read or set, `*` for all of them.
+    pub env_vars: Vec<(String, usize)>,
+}
+
+impl Footprint {
+    pub fn push_program(&mut self, word: Option<&str>, line: usize) {
+        if let Some(word) = word {
+            self.programs.push((program_name(word).to_string(), line));
+        }
+    }
+
+    /// Adds `word` as a path, words that can't be resolved before running are left out.
+    pub fn push_path(&mut self, workspace: &Workspace, word: &str, line: usize) {
+        if let Some(path) = workspace.resolve(word) {
+            self.paths.push((path, line));
+        }
+    }
+
+    /// Adds what nested code uses, on the line of the command running it.
+    pub fn extend_at(&mut self, nested: Footprint, line: usize) {
+        self.programs.extend(nested.programs.into_iter().map(|(program, _)| (program, line)));
+        self.paths.extend(nested.paths.into_iter().map(|(path, _)| (path, line)));
+        self.env_vars.extend(nested.env_vars.into_iter().map(|(name, _)| (name, line)));
+    }
+}
+
+/// Splits `code` into pipelines of simple commands.
+pub fn parse(code: &str) -> Vec<Pipeline> {
+    let tokens = Lexer { chars: code.chars().collect(), pos: 0, line: 1, heredocs: Vec::new(), tokens: Vec::new() }.tokenize();
+
+    let mut pipelines = Vec::new();
+    let mut pipeline = Pipeline::default();
+    let mut command = SimpleCommand::default();
+    let mut redirection: Option<String> = None;
+    let finish_command = |pipeline: &mut Pipeline, command: &mut SimpleCommand| {
+        let command = std::mem::take(command);
+        if !command.words.is_empty() || !command.redirections.is_empty() {
+            pipeline.commands.push(command);
+        }
+    };
+    for token in tokens {
+        match token.kind {
+            TokenKind::Word => {
+                if command.words.is_empty() && command.redirections.is_empty() {
+                    command.line = token.line;
+                }
+                command.substitutions.extend(token.substitutions);
+                match redirection.take() {
+                    Some(operator) => command.redirections.push(Redirection { operator, target: token.text }),
+                    None => command.words.push(token.text),
+                }
+            }
+            TokenKind::Redirection => {
+                if command.words.is_empty() && command.redirections.is_empty() {
+                    command.line = token.line;
+                }
+                redirection = Some(token.text);
+            }
+            TokenKind::Operator => {
+                redirection = None;
+                finish_command(&mut pipeline, &mut command);
+                if token.text != "|" && token.text != "|&" {
+                    pipelines.push(std::mem::take(&mut pipeline));
+                }
+            }
+        }
+    }
+    finish_command(&mut pipeline, &mut command);
+    pipelines.push(pipeline);
+    pipelines.retain(|pipeline| !pipeline.commands.is_empty());
+    pipelines
+}
+
+/// A command of a script as the shell would run it, see [`resolve`].
+#[derive(Debug, Clone)]
+pub struct ResolvedCommand {
+    pub command: SimpleCommand,
+    /// Programs it runs, `sudo env FOO=1 rm -rf x` runs `sudo`, `env` and `rm`. Empty if it only
+    /// assigns variables or redirects.
+    pub programs: Vec<String>,
+    /// Arguments of the last of `programs`.
+    pub args: Vec<String>,
+    /// Where it runs, following the `cd`s before it.
+    pub workspace: Workspace,
+    /// Line it is reported on, that of the outermost command for nested code, starting at 1.
+    pub line: usize,
+    /// Pipeline it is part of, numbered across the nested code too.
+    pub pipeline: usize,
+    /// Code it runs through `$(...)`, `eval` or `sh -c`, whose commands come after it.
+    pub nested: Vec<String>,
+}
+
+impl ResolvedCommand {
+    /// Name of the program it ends up running.
+    pub fn name(&self) -> Option<&str> {
+        self.programs.last().map(String::as_str)
+    }
+}
+
+/// Every command shell `code` run from `workspace` runs, in order, looking into `$(...)`,
+/// `eval` and `sh -c` up to [`MAX_DEPTH`] deep and following `cd`.
+///
+/// Both the safe mode [`scan`] and the policy [`footprint`] are read off this list, so they
+/// agree on what the code runs.
+pub fn resolve(code: &str, workspace: &Workspace) -> Vec<ResolvedCommand> {
+    let mut resolver = Resolver { commands: Vec::new(), pipelines: 0 };
+    resolver.script(code, &mut workspace.clone(), None, 0);
+    resolver.commands
+}
+
+struct Resolver {
+    commands: Vec<ResolvedCommand>,
+    pipelines: usize,
+}
+
+impl Resolver {
+    /// Resolves the commands of `code`, reported on `outer_line` if it is nested.
+    fn script(&mut self, code: &str, workspace: &mut Workspace, outer_line: Option<usize>, depth: usize) {
+        for pipeline in parse(code) {
+            let id = self.pipelines;
+            self.pipelines += 1;
+            for command in pipeline.commands {
+                self.command(command, workspace, outer_line, id, depth);
+            }
+        }
+    }
+
+    fn command(
+        &mut self,
+        command: SimpleCommand,
+        workspace: &mut Workspace,
+        outer_line: Option<usize>,
+        pipeline: usize,
+        depth: usize,
+    ) {
+        let owned = |words: Vec<&str>| words.into_iter().map(String::from).collect::<Vec<_>>();
+        let (programs, args) = match programs(&command.words) {
+            Some((programs, args)) => (owned(programs), owned(args)),
+            None => Default::default(),
+        };
+        let mut nested = Vec::new();
+        if depth < MAX_DEPTH {
+            nested.extend(command.substitutions.iter().cloned());
+            match programs.last().map(String::as_str) {
+                Some("eval") => nested.push(args.join(" ")),
+                Some(name) if SHELLS.contains(&name) => {
+                    let command_flag = |arg: &String| arg.starts_with('-') && !arg.starts_with("--") && arg.contains('c');
+                    if let Some(position) = args.iter().position(command_flag) {
+                        nested.extend(args.get(position + 1).cloned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        let resolved = ResolvedCommand {
+            line: outer_line.unwrap_or(command.line),
+            command,
+            workspace: workspace.clone(),
+            pipeline,
+            nested,
+            programs,
+            args,
+        };
+        if resolved.name() == Some("cd") {
+            if let Some(cwd) = workspace.resolve(cd_target(&resolved.args)) {
+                workspace.cwd = cwd;
+            }
+        }
+        let (line, nested_workspace, scripts) = (resolved.line, resolved.workspace.clone(), resolved.nested.clone());
+        self.commands.push(resolved);
+        for script in &scripts {
+            // `cd` inside a subshell doesn't change the directory of the outer script.
+            self.script(script, &mut nested_workspace.clone(), Some(line), depth + 1);
+        }
+    }
+}
+
+/// Directory `cd` with `args` changes to.
+fn cd_target(args: &[String]) -> &str {
+    args.iter().find(|arg| !arg.starts_with('-')).map_or("~", String::as_str)
+}
+
+/// Programs, paths and environment variables used by shell `code`, looking into `$(...)`,
+/// `eval` and `sh -c` and following `cd`.
+pub fn footprint(code: &str, workspace: &Workspace) -> Footprint {
+    static VARIABLE: OnceLock<Regex> = OnceLock::new();
+    let variable = VARIABLE.get_or_init(|| Regex::new(r"\$\{?([A-Za-z_][A-Za-z0-9_]*)").unwrap());
+
+    let mut footprint = Footprint::default();
+    for resolved in resolve(code, workspace) {
+        let (command, workspace, line) = (&resolved.command, &resolved.workspace, resolved.line);
+        for word in command.words.iter().chain(command.redirections.iter().map(|redirection| &redirection.target)) {
+            footprint.env_vars.extend(variable.captures_iter(word).map(|captures| (captures[1].to_string(), line)));
+        }
+        for word in command.words.iter().take_while(|word| is_assignment(word)) {
+            footprint.env_vars.extend(word.split_once('=').map(|(name, _)| (name.to_string(), line)));
+        }
+        for redirection in command.redirections.iter().filter(|redirection| !redirection.operator.ends_with('&')) {
+            footprint.push_path(workspace, &redirection.target, line);
+        }
+
+        let Some(name) = resolved.name() else {
+            continue;
+        };
+        footprint.programs.extend(resolved.programs.iter().map(|program| (program.clone(), line)));
+        let args = &resolved.args;
+        for arg in args {
+            // `of=/dev/sda` and `--file=~/.ssh/id_rsa` name a path after the `=`.
+            let path = match arg.split_once('=') {
+                Some((_, value)) => value,
+                None if arg.starts_with('-') => continue,
+                None => arg,
+            };
+            footprint.push_path(workspace, path, line);
+        }
+        match name {
+            // Without arguments these print every variable.
+            "env" | "export" | "printenv" | "declare" if args.iter().all(|arg| arg.starts_with('-')) => {
+                footprint.env_vars.push(("*".to_string(), line));
+            }
+            "set" if args.is_empty() => {
+                footprint.env_vars.push(("*".to_string(), line));
+            }
+            "export" | "printenv" | "unset" | "declare" | "readonly" | "local" => {
+                let names = args.iter().filter(|arg| !arg.starts_with('-')).map(|arg| arg.split('=').next().unwrap_or(arg));
+                footprint.env_vars.extend(names.map(|name| (name.to_string(), line)));
+            }
+            _ => {}
+        }
+    }
+    footprint
+}
+
+/// Scans shell `code` for risky commands, redirections and pipelines.
+pub fn scan(code: &str, workspace: &Workspace) -> Scan {
+    static FORK_BOMB: OnceLock<Regex> = OnceLock::new();
+    let fork_bomb = FORK_BOMB.get_or_init(|| Regex::new(r":\s*\(\s*\)\s*\{\s*:\s*\|\s*:\s*&").unwrap());
+    let fork_bomb_finding = || Finding::new(RiskLevel::High, "a fork bomb starts processes until the machine hangs");
+
+    let mut scan = Scan::default();
+    if fork_bomb.is_match(code) {
+        scan.push(fork_bomb_finding());
+    }
+    let commands = resolve(code, workspace);
+    // The last downloader of each pipeline, whose output the commands after it read.
+    let mut downloaders: HashMap<usize, &str> = HashMap::new();
+    for command in &commands {
+        if command.nested.iter().any(|nested| fork_bomb.is_match(nested)) {
+            scan.push(fork_bomb_finding().at_line(command.line));
+        }
+        let Some(name) = scan_command(command, &mut scan) else {
+            continue;
+        };
+        if let (Some(downloader), true) = (downloaders.get(&command.pipeline), INTERPRETERS.contains(&name)) {
+            scan.push(
+                Finding::new(RiskLevel::High, format!("pipes a download from `{downloader}` into `{name}`, running unseen code"))
+                    .at_line(command.line),
+            );
+        }
+        if DOWNLOADERS.contains(&name) {
+            downloaders.insert(command.pipeline, name);
+        }
+    }
+    scan
+}
+
+/// Scans one command and returns the name of the program it runs.
+fn scan_command<'a>(resolved: &'a ResolvedCommand, scan: &mut Scan) -> Option<&'a str> {
+    let (command, workspace, line) = (&resolved.command, &resolved.workspace, resolved.line);
+    let push = |scan: &mut Scan, finding: Finding| scan.push(finding.at_line(line));
+
+    for redirection in &command.redirections {
+        // `>&2` duplicates a file descriptor, it does not name a file.
+        let duplicates_fd = redirection.operator.ends_with('&');
+        if redirection.operator.contains('>') && !duplicates_fd {
+            if let Some(finding) = workspace.check_write("a redirection", &redirection.target) {
+                push(scan, finding);
+            }
+        }
+    }
+
+    let name = resolved.name()?;
+    for program in resolved.programs.iter().filter(|program| ROOT_COMMANDS.contains(&program.as_str())) {
+        push(scan, Finding::new(RiskLevel::High, format!("runs commands as root with `{program}`")));
+    }
+    if name == "su" {
+        return Some(name);
+    }
+
+    let args: Vec<&str> = resolved.args.iter().map(String::as_str).collect();
+    let targets: Vec<&str> = args.iter().copied().filter(|arg| !arg.starts_with('-')).collect();
+    match name {
+        "cd" => {
+            let target = cd_target(&resolved.args);
+            if workspace.resolve(target).is_none() {
+                push(scan, Finding::new(RiskLevel::Low, format!("changes to `{target}`, which can't be resolved before running")));
+            }
+        }
+        "rm" => scan_rm(&args, workspace, scan, line),
+        "dd" => {
+            for target in args.iter().filter_map(|arg| arg.strip_prefix("of=")) {
+                if let Some(finding) = workspace.check_write("dd", target) {
+                    push(scan, finding);
+                }
+            }
+        }
+        name if name.starts_with("mkfs") || DESTRUCTIVE_COMMANDS.contains(&name) => {
+            push(scan, Finding::new(RiskLevel::High, format!("`{name}` can wipe disks or stop the machine")));
+        }
+        name if SERVICE_COMMANDS.contains(&name) => {
+            push(scan, Finding::new(RiskLevel::Medium, format!("`{name}` changes system services")));
+        }
+        name if KILL_COMMANDS.contains(&name) => {
+            push(scan, Finding::new(RiskLevel::Medium, format!("`{name}` stops other processes")));
+        }
+        name if WRITE_ALL_COMMANDS.contains(&name) => {
+            for target in &targets {
+                if let Some(finding) = workspace.check_write(name, target) {
+                    push(scan, finding);
+                }
+            }
+        }
+        name if CHANGE_COMMANDS.contains(&name) => {
+            for target in targets.iter().skip(1) {
+                if let Some(finding) = workspace.check_write(name, target) {
+                    push(scan, finding);
+                }
+            }
+        }
+        name if COPY_COMMANDS.contains(&name) && targets.len() > 1 => {
+            if let Some(finding) = workspace.check_write(name, targets[targets.len() - 1]) {
+                push(scan, finding);
+            }
+        }
+        _ => {}
+    }
+
+    // A shell or interpreter fed a download, like `bash <(curl ...)`.
+    if INTERPRETERS.contains(&name)
+        && command.substitutions.iter().any(|substitution| DOWNLOADERS.iter().any(|downloader| substitution.contains(downloader)))
+    {
+        push(scan, Finding::new(RiskLevel::High, format!("runs a download with `{name}`, running unseen code")));
+    }
+    Some(name)
+}
+
+fn scan_rm(args: &[&str], workspace: &Workspace, scan: &mut Scan, line: usize) {
+    let mut recursive = false;
+    let mut force = false;
+    let mut targets = Vec::new();
+    let mut options_ended = false;
+    for arg in args {
+        match *arg {
+            "--" if !options_ended => options_ended = true,
+            "--recursive" if !options_ended => recursive = true,
+            "--force" if !options_ended => force = true,
+            arg if !options_ended && arg.starts_with('-') && !arg.starts_with("--") => {
+                recursive |= arg.contains(['r', 'R']);
+                force |= arg.contains('f');
+            }
+            arg if !options_ended && arg.starts_with("--") => {}
+            arg => targets.push(arg),
+        }
+    }
+
+    if recursive && force {
+        scan.push(Finding::new(RiskLevel::High, "`rm -rf` deletes files recursively without asking").at_line(line));
+    } else if recursive {
+        scan.push(Finding::new(RiskLevel::Medium, "`rm -r` deletes directories recursively").at_line(line));
+    }
+    for target in targets {
+        let finding = match workspace.resolve(target) {
+            // Deleting the workspace itself or a directory holding it.
+            Some(path) if recursive && (workspace.root.starts_with(&path) || workspace.is_system_path(&path)) => {
+                Some(Finding::new(RiskLevel::High, format!("rm deletes `{}` and everything in it", path.display())))
+            }
+            None if recursive => Some(Finding::new(
+                RiskLevel::High,
+                format!("rm deletes `{target}` recursively, which can't be resolved before running"),
+            )),
+            _ => workspace.check_write("rm", target),
+        };
+        if let Some(finding) = finding {
+            scan.push(finding.at_line(line));
+        }
+    }
+}
+
+/// Programs a command runs and the arguments of the last one, `sudo env FOO=1 rm -rf x` runs
+/// `sudo`, `env` and `rm`.
+fn programs(words: &[String]) -> Option<(Vec<&str>, Vec<&str>)> {
+    let mut words = words.iter().map(String::as_str).skip_while(|word| is_assignment(word) || RESERVED_WORDS.contains(word));
+    let mut programs = vec![program_name(words.next()?)];
+    let mut args: Vec<&str> = words.collect();
+    // Look through `sudo`, `env` and the like at the command they run, `su` takes a user instead.
+    while programs.last().is_some_and(|name| *name != "su" && (ROOT_COMMANDS.contains(name) || WRAPPERS.contains(name))) {
+        let mut rest = args.into_iter().skip_while(|arg| arg.starts_with('-') || is_assignment(arg) || arg.parse::<f64>().is_ok());
+        let Some(next) = rest.next() else {
+            return Some((programs, Vec::new()));
+        };
+        programs.push(program_name(next));
+        args = rest.collect();
+    }
+    Some((programs, args))
+}
+
+/// `/usr/bin/rm` runs `rm`.
+fn program_name(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn risk(code: &str) -> RiskLevel {
+        scan(code, &Workspace::fixture()).risk()
+    }
+
+    #[test]
+    fn test_parse_pipelines_and_redirections() {
+        let pipelines = parse("FOO=1 grep -v 'a b' file.txt 2>/dev/null | sort > \"out put.txt\" && echo $(date)\n# rm -rf /\nls");
+        assert_eq!(pipelines.len(), 3);
+        let grep = &pipelines[0].commands[0];
+        assert_eq!(grep.words, ["FOO=1", "grep", "-v", "a b", "file.txt"]);
+        assert_eq!(grep.redirections, [Redirection { operator: "2>".to_string(), target: "/dev/null".to_string() }]);
+        assert_eq!(pipelines[0].commands[1].redirections[0].target, "out put.txt");
+        assert_eq!(pipelines[1].commands[0].substitutions, ["date"]);
+        assert_eq!(pipelines[2].commands[0].line, 3);
+    }
+
+    #[test]
+    fn test_heredoc_body_is_not_code() {
+        let pipelines = parse("cat <<EOF > notes.txt\nrm -rf /\nEOF\necho done");
+        assert_eq!(pipelines.len(), 2);
+        assert_eq!(pipelines[1].commands[0].words, ["echo", "done"]);
+        assert_eq!(pipelines[1].commands[0].line, 4);
+    }
+
+    #[test]
+    fn test_resolve_nested_commands() {
+        let workspace = Workspace::fixture();
+        let commands = resolve("ls\nsudo env FOO=1 bash -c 'cd /etc; rm -rf x' | cat", &workspace);
+        let names: Vec<_> = commands.iter().map(|command| (command.name().unwrap(), command.line)).collect();
+        assert_eq!(names, [("ls", 1), ("bash", 2), ("cd", 2), ("rm", 2), ("cat", 2)]);
+        assert_eq!(commands[1].programs, ["sudo", "env", "bash"]);
+        assert_eq!(commands[3].workspace.cwd, PathBuf::from("/etc"));
+        assert_eq!(commands[4].workspace.cwd, workspace.cwd);
+        // The pipeline of `bash` goes on with `cat`, the nested code is one of its own.
+        assert_eq!(commands[1].pipeline, commands[4].pipeline);
+        assert_ne!(commands[1].pipeline, commands[3].pipeline);
+    }
+
+    #[test]
+    fn test_harmless_commands() {
+        assert_eq!(risk("ls -la | grep foo > matches.txt"), RiskLevel::Low);
+        assert_eq!(risk("mkdir -p build && cp a.txt build/ 2>&1"), RiskLevel::Low);
+        assert_eq!(risk("echo hi > /dev/null; echo tmp > /tmp/scratch.txt"), RiskLevel::Low);
+        assert_eq!(risk("rm notes.txt"), RiskLevel::Low);
+    }
+
+    #[test]
+    fn test_risky_commands() {
+        assert_eq!(risk("rm -rf build"), RiskLevel::High);
+        assert_eq!(risk("rm -r build"), RiskLevel::Medium);
+        assert_eq!(risk("rm -r ~"), RiskLevel::High);
+        assert_eq!(risk("rm -r ../"), RiskLevel::High);
+        assert_eq!(risk("rm -r \"$DIR/\""), RiskLevel::High);
+        assert_eq!(risk("sudo apt install ripgrep"), RiskLevel::High);
+        assert_eq!(risk("curl -fsSL https://example.com/install.sh | sh"), RiskLevel::High);
+        assert_eq!(risk("wget -qO- https://example.com/x | sudo bash"), RiskLevel::High);
+        assert_eq!(risk("bash <(curl -s https://example.com/x)"), RiskLevel::High);
+        assert_eq!(risk("bash -c 'rm -rf /'"), RiskLevel::High);
+        assert_eq!(risk(":(){ :|:& };:"), RiskLevel::High);
+    }
+
+    #[test]
+    fn test_writes_outside_cwd() {
+        assert_eq!(risk("echo x >> ~/.bashrc"), RiskLevel::Medium);
+        assert_eq!(risk("echo x > /etc/hosts"), RiskLevel::High);
+        assert_eq!(risk("cp config.toml ../other/"), RiskLevel::Medium);
+        assert_eq!(risk("echo x | tee /usr/local/bin/tool"), RiskLevel::High);
+        // The directory changes with `cd`.
+        assert_eq!(risk("cd /etc\necho x > hosts"), RiskLevel::High);
+        assert_eq!(risk("cd sub && touch a.txt"), RiskLevel::Low);
+    }
+
+    #[test]
+    fn test_finding_lines() {
+        let scan = scan("ls\nsudo reboot", &Workspace::fixture());
+        assert!(scan.findings.iter().all(|finding| finding.line == Some(2)));
+        assert_eq!(scan.findings.len(), 2);
+    }
+}
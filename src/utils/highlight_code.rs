@@ -0,0 +1,22 @@
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Style, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+/// Prints `code` to the terminal, highlighted by the syntax registered for `extension`.
+pub fn print_highlighted_code(code: &str, extension: &str) {
+    let ps = SyntaxSet::load_defaults_newlines();
+    let ts = ThemeSet::load_defaults();
+    let syntax = ps.find_syntax_by_extension(extension).unwrap_or_else(|| ps.find_syntax_plain_text());
+    let mut h = HighlightLines::new(syntax, &ts.themes["Solarized (dark)"]);
+
+    println!("\n =================================================");
+    let hl_code = "\n".to_string() + code + "\n";
+    for line in LinesWithEndings::from(&hl_code) {
+        let ranges: Vec<(Style, &str)> = h.highlight_line(line, &ps).unwrap_or_default();
+        let escaped = as_24_bit_terminal_escaped(&ranges[..], false);
+        print!("{}", escaped);
+    }
+    // Reset the terminal colours.
+    println!("\x1b[0m\n =================================================");
+}
@@ -1,5 +1,8 @@
 pub mod get_user_info_string;
 pub mod check_env;
+pub mod highlight_code;
+pub mod process_tree;
 
 pub use get_user_info_string::get_user_info_string;
-pub use check_env::check_environments;
\ No newline at end of file
+pub use check_env::check_environments;
+pub use highlight_code::print_highlighted_code;
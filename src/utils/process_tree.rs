@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Pids of the direct children of `pid`, read from `/proc`. Empty where `/proc` is unavailable.
+pub fn child_pids(pid: u32) -> Vec<u32> {
+    task_children(pid).unwrap_or_else(|| {
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .filter(|&candidate| parent_pid(candidate) == Some(pid))
+            .collect()
+    })
+}
+
+/// `pid` and every process below it, read from `/proc`. Empty where `/proc` is unavailable.
+///
+/// Walks down from `pid` where the kernel lists the children of each thread, otherwise reads
+/// the parent of every process once.
+pub fn process_tree(pid: u32) -> Vec<u32> {
+    if task_children(pid).is_some() {
+        let mut tree = vec![pid];
+        let mut next = 0;
+        while let Some(&parent) = tree.get(next) {
+            tree.extend(task_children(parent).unwrap_or_default());
+            next += 1;
+        }
+        return tree;
+    }
+
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for candidate in entries.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok()) {
+        if let Some(parent) = parent_pid(candidate) {
+            children.entry(parent).or_default().push(candidate);
+        }
+    }
+    let mut tree = vec![pid];
+    let mut next = 0;
+    while let Some(&parent) = tree.get(next) {
+        tree.extend(children.get(&parent).into_iter().flatten());
+        next += 1;
+    }
+    tree
+}
+
+/// Children of `pid` from `/proc/<pid>/task/*/children`, `None` if the kernel doesn't have
+/// those files. A process that exited has none.
+fn task_children(pid: u32) -> Option<Vec<u32>> {
+    let Ok(tasks) = fs::read_dir(format!("/proc/{pid}/task")) else {
+        return Path::new("/proc/self/task").is_dir().then(Vec::new);
+    };
+    let mut children: Option<Vec<u32>> = None;
+    for task in tasks.filter_map(Result::ok) {
+        if let Ok(pids) = fs::read_to_string(task.path().join("children")) {
+            children.get_or_insert_with(Vec::new).extend(pids.split_whitespace().filter_map(|child| child.parse::<u32>().ok()));
+        }
+    }
+    children
+}
+
+/// User and system CPU time of `pid`, including its children that have exited and been waited
+/// for, from `/proc/<pid>/stat`.
+pub fn cpu_times(pid: u32) -> Option<(Duration, Duration)> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    // utime, stime, cutime and cstime are fields 14 to 17, counting from 1 before the name.
+    let ticks: Vec<u64> = fields.split_whitespace().skip(11).take(4).map(|field| field.parse().ok()).collect::<Option<_>>()?;
+    let [utime, stime, cutime, cstime] = ticks[..] else {
+        return None;
+    };
+    // SAFETY: sysconf only reads a configuration value.
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+    let time = |ticks: u64| Duration::from_secs_f64(ticks as f64 / ticks_per_sec);
+    Some((time(utime + cutime), time(stime + cstime)))
+}
+
+/// Resident memory of `pid` in bytes, from `/proc/<pid>/status`.
+pub fn rss_bytes(pid: u32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line["VmRSS:".len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Working directory of `pid`, `None` where `/proc` is unavailable.
+pub fn process_cwd(pid: u32) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{pid}/cwd")).ok()
+}
+
+/// Parent pid from `/proc/<pid>/stat`.
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name may contain spaces, the fields after it are well formed.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_child_pids() {
+        let mut child = std::process::Command::new("sleep").arg("5").spawn().unwrap();
+        assert!(child_pids(std::process::id()).contains(&child.id()));
+        // Only kernels built with `CONFIG_PROC_CHILDREN` list them.
+        if let Some(children) = task_children(std::process::id()) {
+            assert!(children.contains(&child.id()));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_process_tree_and_usage() {
+        let mut child = std::process::Command::new("sleep").arg("5").spawn().unwrap();
+        let tree = process_tree(std::process::id());
+        assert_eq!(tree[0], std::process::id());
+        assert!(tree.contains(&child.id()));
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert!(rss_bytes(std::process::id()).unwrap() > 0);
+        assert!(cpu_times(std::process::id()).is_some());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_process_cwd() {
+        assert_eq!(process_cwd(std::process::id()), std::env::current_dir().ok());
+    }
+}